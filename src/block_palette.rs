@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::turtle_state::Coord;
+
+/// Minecraft id and block state of a block, as the turtle sees it when inspecting. The simulator
+/// names the blocks of its world the same way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockIdentity {
+    pub name: String,
+    #[serde(default)]
+    pub state: Map<String, Value>
+}
+
+impl BlockIdentity {
+    /// A block without any state
+    pub fn new(name: &str) -> Self {
+        BlockIdentity {name: name.to_string(), state: Map::new()}
+    }
+
+    pub fn with_state(name: &str, state: Map<String, Value>) -> Self {
+        BlockIdentity {name: name.to_string(), state}
+    }
+
+    /// A fluid block, level 0 is the source and higher levels are flowing
+    pub fn fluid(name: &str, level: u32) -> Self {
+        let mut state = Map::new();
+        state.insert("level".to_string(), Value::from(level));
+        BlockIdentity {name: name.to_string(), state}
+    }
+
+    /// Whether the block is a fluid source rather than flowing fluid
+    pub fn is_source(&self) -> bool {
+        self.state.get("level").and_then(Value::as_u64).unwrap_or(0) == 0
+    }
+
+    /// Whether the block falls when there is nothing below it, like sand
+    pub fn falls(&self) -> bool {
+        matches!(self.name.as_str(), "minecraft:sand"|"minecraft:red_sand"|"minecraft:gravel"|"minecraft:anvil")
+            || self.name.ends_with("_concrete_powder")
+    }
+}
+
+/// An entry of `blocks.json`, which names the blocks of a test world or of a saved world
+#[derive(Serialize, Deserialize)]
+pub struct BlockEntry {
+    pub pos: Coord,
+    #[serde(flatten)]
+    pub block: BlockIdentity
+}
+
+/// Refers to an identity of a `BlockPalette`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockKey(u32);
+
+/// Interns block identities, so that every distinct name and state is stored only once however
+/// many blocks of it there are
+#[derive(Debug, Default)]
+pub struct BlockPalette {
+    identities: Vec<BlockIdentity>,
+    keys: HashMap<(String, String), BlockKey> // name and the state as json
+}
+
+impl BlockPalette {
+    pub fn new() -> Self {
+        BlockPalette::default()
+    }
+
+    pub fn intern(&mut self, name: &str, state: &Map<String, Value>) -> BlockKey {
+        let id = (name.to_string(), Value::Object(state.clone()).to_string());
+        if let Some(key) = self.keys.get(&id) {
+            return *key;
+        }
+        let key = BlockKey(self.identities.len() as u32);
+        self.identities.push(BlockIdentity::with_state(name, state.clone()));
+        self.keys.insert(id, key);
+        key
+    }
+
+    pub fn get(&self, key: BlockKey) -> &BlockIdentity {
+        &self.identities[key.0 as usize]
+    }
+
+    /// Keys of every state of the block
+    pub fn keys_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = BlockKey> + 'a {
+        self.identities.iter().enumerate()
+            .filter(move |(_, identity)| identity.name == name)
+            .map(|(i, _)| BlockKey(i as u32))
+    }
+
+    pub fn len(&self) -> usize {
+        self.identities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.identities.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identities_are_interned() {
+        let mut palette = BlockPalette::new();
+        let mut axis = Map::new();
+        axis.insert("axis".to_string(), Value::from("y"));
+        let ore = palette.intern("minecraft:diamond_ore", &Map::new());
+        let log = palette.intern("minecraft:oak_log", &axis);
+        assert_eq!(ore, palette.intern("minecraft:diamond_ore", &Map::new()));
+        assert_eq!(log, palette.intern("minecraft:oak_log", &axis));
+        axis.insert("axis".to_string(), Value::from("x"));
+        let log_x = palette.intern("minecraft:oak_log", &axis);
+        assert_ne!(log, log_x);
+        assert_eq!(3, palette.len());
+        assert_eq!("x", palette.get(log_x).state["axis"]);
+        assert_eq!(vec![log, log_x], palette.keys_of("minecraft:oak_log").collect::<Vec<BlockKey>>());
+    }
+}
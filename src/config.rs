@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use anyhow::{anyhow, Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::turtle_state::StateSerializationPolicy;
+use crate::run_history::RunHistory;
+
+/// How much the server reports about connections and the messages it exchanges. The debug output
+/// of the turtle logic, like location and pathfinding updates, is printed only at Debug.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Debug
+}
+
+impl Verbosity {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "quiet" | "0" => Ok(Verbosity::Quiet),
+            "normal" | "1" => Ok(Verbosity::Normal),
+            "debug" | "2" => Ok(Verbosity::Debug),
+            x => Err(anyhow!("Invalid verbosity: {}", x))
+        }
+    }
+
+    /// The verbosity of the whole process, used by `report!`
+    pub fn current() -> Self {
+        match VERBOSITY.load(Ordering::Relaxed) {
+            0 => Verbosity::Quiet,
+            1 => Verbosity::Normal,
+            _ => Verbosity::Debug
+        }
+    }
+
+    pub fn set_current(self) {
+        VERBOSITY.store(self as u8, Ordering::Relaxed);
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Prints like `println!` if the verbosity of the process is at least the given level,
+/// e.g. `report!(Debug, "Updating gps {:?}", precision)`
+#[macro_export]
+macro_rules! report {
+    ($level:ident, $($arg:tt)*) => {
+        if $crate::config::Verbosity::current() >= $crate::config::Verbosity::$level {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Settings of a single server instance. Every field has a default, so a config file only needs
+/// to list the values it changes. Command-line flags override the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: String,
+    /// Policy used for turtles which have no entry in `turtle_state`
+    pub state: StateSerializationPolicy,
+    /// Per-turtle overrides of `state`, keyed by turtle id
+    pub turtle_state: HashMap<String, StateSerializationPolicy>,
+    pub runs_dir: String,
+    pub verbosity: Verbosity
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "25.75.103.40:80".to_string(),
+            state: StateSerializationPolicy::LoadAndSave {load_dir: "state".to_string(), save_dir: "state".to_string()},
+            turtle_state: HashMap::new(),
+            runs_dir: RunHistory::DEFAULT_DIR.to_string(),
+            verbosity: Verbosity::Normal
+        }
+    }
+}
+
+const USAGE: &str = "Usage: turtlers [--config <file.json|file.toml>] [--bind <addr:port>] [--state-dir <dir>]
+                [--state-policy <load-and-save|load-only|save-only|none>] [--runs-dir <dir>]
+                [--verbosity <quiet|normal|debug>]
+
+--verbosity sets how much the server reports about connections and messages,
+the debug output of the turtle logic is printed only at debug.";
+
+/// Returned by `ServerConfig::from_args` for --help, whose message is the usage
+#[derive(Debug, Error)]
+#[error("{}", USAGE)]
+pub struct HelpRequested;
+
+impl ServerConfig {
+    /// Reads a TOML config file if the path ends with .toml, otherwise a JSON one
+    pub fn from_file(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file {}", path))?;
+        let config = if path.ends_with(".toml") {
+            toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path))?
+        } else {
+            serde_json::from_str(&contents).with_context(|| format!("Invalid config file {}", path))?
+        };
+        Ok(config)
+    }
+
+    /// Builds the configuration from command-line arguments, excluding the program name.
+    /// `--config` is applied first regardless of its position, the remaining flags override it.
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut flags: Vec<(&str, &str)> = vec![];
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(HelpRequested.into());
+            }
+            let value = iter.next().ok_or_else(|| anyhow!("Missing value for {}\n{}", flag, USAGE))?;
+            flags.push((flag.as_str(), value.as_str()));
+        }
+
+        let mut config = match flags.iter().find(|(flag, _)| *flag == "--config") {
+            Some((_, path)) => ServerConfig::from_file(path)?,
+            None => ServerConfig::default()
+        };
+
+        let mut state_dir = None;
+        let mut state_policy = None;
+        for (flag, value) in flags {
+            match flag {
+                "--config" => {},
+                "--bind" => config.bind = value.to_string(),
+                "--state-dir" => state_dir = Some(value.to_string()),
+                "--state-policy" => state_policy = Some(value),
+                "--runs-dir" => config.runs_dir = value.to_string(),
+                "--verbosity" => config.verbosity = Verbosity::parse(value)?,
+                x => return Err(anyhow!("Unknown argument: {}\n{}", x, USAGE))
+            }
+        }
+        if state_dir.is_some() || state_policy.is_some() {
+            let dir = state_dir.or_else(|| config.state.dir().map(str::to_string)).unwrap_or_else(|| "state".to_string());
+            let policy = state_policy.unwrap_or_else(|| config.state.name());
+            config.state = StateSerializationPolicy::parse(policy, &dir)?;
+        }
+        Ok(config)
+    }
+
+    pub fn state_policy(&self, turtle_id: &str) -> StateSerializationPolicy {
+        self.turtle_state.get(turtle_id).unwrap_or(&self.state).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        let config = ServerConfig::from_args(&[]).unwrap();
+        assert_eq!(ServerConfig::default(), config);
+        assert_eq!("runs", config.runs_dir);
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        let config = ServerConfig::from_args(&args(&[
+            "--bind", "127.0.0.1:8080", "--state-dir", "state2", "--state-policy", "load-only",
+            "--runs-dir", "runs2", "--verbosity", "debug"
+        ])).unwrap();
+        assert_eq!("127.0.0.1:8080", config.bind);
+        assert_eq!(StateSerializationPolicy::LoadOnly {load_dir: "state2".to_string()}, config.state);
+        assert_eq!("runs2", config.runs_dir);
+        assert_eq!(Verbosity::Debug, config.verbosity);
+    }
+
+    #[test]
+    fn state_dir_keeps_policy() {
+        let config = ServerConfig::from_args(&args(&["--state-dir", "other"])).unwrap();
+        assert_eq!(StateSerializationPolicy::LoadAndSave {load_dir: "other".to_string(), save_dir: "other".to_string()}, config.state);
+    }
+
+    #[test]
+    fn per_turtle_policy_from_json() {
+        let json = r#"{
+            "bind": "0.0.0.0:9000",
+            "turtle_state": {
+                "7": {"policy": "none"},
+                "8": {"policy": "load-only", "load_dir": "state8"},
+                "9": {"policy": "save_only", "save_dir": "state9"}
+            }
+        }"#;
+        let config: ServerConfig = serde_json::from_str(json).unwrap();
+        assert_eq!("0.0.0.0:9000", config.bind);
+        assert_eq!(StateSerializationPolicy::None, config.state_policy("7"));
+        assert_eq!(StateSerializationPolicy::LoadOnly {load_dir: "state8".to_string()}, config.state_policy("8"));
+        assert_eq!(StateSerializationPolicy::SaveOnly {save_dir: "state9".to_string()}, config.state_policy("9"));
+        let saved = serde_json::to_value(config.state_policy("8")).unwrap();
+        assert_eq!(StateSerializationPolicy::LoadOnly {load_dir: String::new()}.name(), saved["policy"]);
+        assert_eq!(ServerConfig::default().state, config.state_policy("10"));
+    }
+
+    #[test]
+    fn per_turtle_policy_from_toml() {
+        let path = format!("{}/config_test.toml", std::env::temp_dir().display());
+        std::fs::write(&path, r#"
+            bind = "0.0.0.0:9000"
+            verbosity = "quiet"
+
+            [state]
+            policy = "save-only"
+            save_dir = "saved"
+
+            [turtle_state.7]
+            policy = "none"
+        "#).unwrap();
+        let config = ServerConfig::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("0.0.0.0:9000", config.bind);
+        assert_eq!(Verbosity::Quiet, config.verbosity);
+        assert_eq!(StateSerializationPolicy::SaveOnly {save_dir: "saved".to_string()}, config.state_policy("8"));
+        assert_eq!(StateSerializationPolicy::None, config.state_policy("7"));
+    }
+
+    #[test]
+    fn invalid_arguments_are_errors() {
+        assert!(ServerConfig::from_args(&args(&["--bind"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--port", "80"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--state-policy", "sometimes"])).is_err());
+        assert!(ServerConfig::from_args(&args(&["--bind", "127.0.0.1:8080", "--help"])).unwrap_err().is::<HelpRequested>());
+    }
+}
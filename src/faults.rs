@@ -0,0 +1,165 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use thiserror::Error;
+use crate::turtle_action::TurtleAction;
+use crate::turtle_state::Coord;
+
+/// Something going wrong in the simulated world or connection
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Fault {
+    #[error("gps timed out")]
+    GpsTimeout,
+    #[error("a mob obstructed the move")]
+    MobObstruction,
+    #[error("the turtle disconnected before the action")]
+    Disconnect,
+    #[error("the response to the action was lost")]
+    DroppedResponse
+}
+
+/// Probabilities of the faults, checked for each action. Everything is off by default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultConfig {
+    pub gps_timeout: f64,
+    pub mob_obstruction: f64, // a mob appears in front of a move
+    pub mob_steps: u32, // how many actions the mob stays in place
+    pub disconnect: f64,
+    pub dropped_response: f64
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig {
+            gps_timeout: 0.0,
+            mob_obstruction: 0.0,
+            mob_steps: 3,
+            disconnect: 0.0,
+            dropped_response: 0.0
+        }
+    }
+}
+
+/// Decides which faults happen. The same seed and actions always give the same faults.
+#[derive(Debug)]
+pub struct FaultInjector {
+    pub config: FaultConfig,
+    rng: StdRng,
+    mobs: Vec<(Coord, u32)>, // location and the actions left until the mob wanders off
+    injected: Vec<(usize, Fault)>,
+    step: usize
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig, seed: u64) -> Self {
+        FaultInjector {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            mobs: vec![],
+            injected: vec![],
+            step: 0
+        }
+    }
+
+    /// Fault that happens instead of the action. `dest` is where a move would go, if it's free.
+    pub fn before_action(&mut self, action: &TurtleAction, dest: Option<&Coord>) -> Option<Fault> {
+        let fault = if self.rng.gen_bool(self.config.disconnect) {
+            Some(Fault::Disconnect)
+        } else {
+            match (action, dest) {
+                (TurtleAction::GpsLocate {..}, _) if self.rng.gen_bool(self.config.gps_timeout) => Some(Fault::GpsTimeout),
+                (TurtleAction::Move {..}, Some(dest)) if self.mob_at(dest) => Some(Fault::MobObstruction),
+                (TurtleAction::Move {..}, Some(dest)) if self.rng.gen_bool(self.config.mob_obstruction) => {
+                    self.mobs.push((dest.clone(), self.config.mob_steps));
+                    Some(Fault::MobObstruction)
+                },
+                _ => None
+            }
+        };
+        self.record(fault)
+    }
+
+    /// Fault that happens after the action was executed in the world
+    pub fn after_action(&mut self) -> Option<Fault> {
+        let fault = if self.rng.gen_bool(self.config.dropped_response) {
+            Some(Fault::DroppedResponse)
+        } else {
+            None
+        };
+        self.record(fault)
+    }
+
+    /// Ends the action, the mobs move on after they have stayed for long enough
+    pub fn tick(&mut self) {
+        for mob in self.mobs.iter_mut() {
+            mob.1 = mob.1.saturating_sub(1);
+        }
+        self.mobs.retain(|(_, steps)| *steps > 0);
+        self.step += 1;
+    }
+
+    pub fn mob_at(&self, loc: &Coord) -> bool {
+        self.mobs.iter().any(|(mob, _)| mob == loc)
+    }
+
+    /// Kills the mob at the location, returns false if there was none
+    pub fn kill_mob(&mut self, loc: &Coord) -> bool {
+        let before = self.mobs.len();
+        self.mobs.retain(|(mob, _)| mob != loc);
+        self.mobs.len() < before
+    }
+
+    /// The faults so far, with the number of the action they happened in
+    pub fn injected(&self) -> &[(usize, Fault)] {
+        &self.injected
+    }
+
+    fn record(&mut self, fault: Option<Fault>) -> Option<Fault> {
+        if let Some(fault) = &fault {
+            self.injected.push((self.step, fault.clone()));
+        }
+        fault
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turtle_action::{go, gps};
+
+    fn faults_of(seed: u64) -> Vec<(usize, Fault)> {
+        let config = FaultConfig {gps_timeout: 0.5, mob_obstruction: 0.3, disconnect: 0.1, dropped_response: 0.1, ..FaultConfig::default()};
+        let mut injector = FaultInjector::new(config, seed);
+        let dest = Coord::new(1, 0, 0);
+        for i in 0..100 {
+            let action = if i % 2 == 0 {gps::locate()} else {go::forward()};
+            if injector.before_action(&action, Some(&dest)).is_none() {
+                injector.after_action();
+            }
+            injector.tick();
+        }
+        injector.injected().to_vec()
+    }
+
+    #[test]
+    fn faults_are_reproducible() {
+        let faults = faults_of(7);
+        assert_eq!(faults, faults_of(7));
+        assert_ne!(faults, faults_of(8));
+        for kind in &[Fault::GpsTimeout, Fault::MobObstruction, Fault::Disconnect, Fault::DroppedResponse] {
+            assert!(faults.iter().any(|(_, fault)| fault == kind), "No {:?} in {:?}", kind, faults);
+        }
+    }
+
+    #[test]
+    fn mobs_wander_off() {
+        let config = FaultConfig {mob_obstruction: 1.0, mob_steps: 2, ..FaultConfig::default()};
+        let mut injector = FaultInjector::new(config, 0);
+        let dest = Coord::new(1, 0, 0);
+        assert_eq!(Some(Fault::MobObstruction), injector.before_action(&go::forward(), Some(&dest)));
+        injector.tick();
+        injector.config.mob_obstruction = 0.0;
+        assert_eq!(Some(Fault::MobObstruction), injector.before_action(&go::forward(), Some(&dest)));
+        injector.tick();
+        assert!(!injector.mob_at(&dest));
+        assert_eq!(None, injector.before_action(&go::forward(), Some(&dest)));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::turtle_action::{FailureReason, TurtleAction, TurtleActionReturn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuelLevel {
+    Unknown,
+    Known(u32),
+    Unlimited // fuel is disabled in the server config
+}
+
+/// Fuel gained by burning one of the item, None if the item doesn't burn
+pub fn fuel_value(name: &str) -> Option<u32> {
+    match name {
+        "minecraft:lava_bucket" => Some(1000),
+        "minecraft:coal_block" => Some(800),
+        "minecraft:blaze_rod" => Some(120),
+        "minecraft:coal"|"minecraft:charcoal" => Some(80),
+        "minecraft:stick" => Some(5),
+        x if x.ends_with("_planks") || x.ends_with("_log") => Some(15),
+        _ => None
+    }
+}
+
+/// Tracks the fuel of the turtle. Each successful move costs one unit, which is subtracted from the
+/// last known level. Queries replace the estimate with the real value.
+#[derive(Debug, Clone)]
+pub struct FuelState {
+    pub level: FuelLevel,
+    pub limit: FuelLevel
+}
+
+impl Default for FuelState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FuelState {
+    pub fn new() -> Self {
+        FuelState {
+            level: FuelLevel::Unknown,
+            limit: FuelLevel::Unknown
+        }
+    }
+
+    /// Fuel is disabled in the server config
+    pub fn unlimited() -> Self {
+        FuelState {
+            level: FuelLevel::Unlimited,
+            limit: FuelLevel::Unlimited
+        }
+    }
+
+    /// Whether the turtle can do `moves` more moves, None if the fuel level is unknown.
+    pub fn has_fuel_for(&self, moves: u32) -> Option<bool> {
+        match self.level {
+            FuelLevel::Unknown => None,
+            FuelLevel::Known(level) => Some(level >= moves),
+            FuelLevel::Unlimited => Some(true)
+        }
+    }
+
+    /// Fuel that can still be added before reaching the limit, None if either is unknown.
+    pub fn space(&self) -> Option<u32> {
+        match (self.level, self.limit) {
+            (FuelLevel::Known(level), FuelLevel::Known(limit)) => Some(limit.saturating_sub(level)),
+            (FuelLevel::Unlimited, _)|(_, FuelLevel::Unlimited) => Some(0),
+            _ => None
+        }
+    }
+
+    fn from_response(result: &TurtleActionReturn) -> Option<FuelLevel> {
+        match result {
+            TurtleActionReturn::Number(x) => Some(FuelLevel::Known(*x)),
+            TurtleActionReturn::Unlimited => Some(FuelLevel::Unlimited),
+            _ => None
+        }
+    }
+
+    pub fn update(&mut self, action: &TurtleAction, result: &TurtleActionReturn) {
+        match (action, result) {
+            (TurtleAction::Move{..}, TurtleActionReturn::Success) => {
+                if let FuelLevel::Known(level) = self.level {
+                    self.level = FuelLevel::Known(level.saturating_sub(1));
+                }
+            },
+            (TurtleAction::Move{..}, TurtleActionReturn::Failure(FailureReason::OutOfFuel)) => {
+                self.level = FuelLevel::Known(0);
+            },
+            (TurtleAction::Refuel{..}, TurtleActionReturn::Success) if self.level != FuelLevel::Unlimited => {
+                // The amount depends on the burned item, so the level has to be queried again
+                self.level = FuelLevel::Unknown;
+            },
+            (TurtleAction::GetFuelLevel, result) => {
+                if let Some(level) = FuelState::from_response(result) {
+                    self.level = level;
+                }
+            },
+            (TurtleAction::GetFuelLimit, result) => {
+                if let Some(limit) = FuelState::from_response(result) {
+                    self.limit = limit;
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turtle_action::{fuel, go, turn};
+
+    #[test]
+    fn moves_consume_fuel() {
+        let mut state = FuelState::new();
+        state.update(&go::forward(), &TurtleActionReturn::Success);
+        assert_eq!(FuelLevel::Unknown, state.level);
+        assert_eq!(None, state.has_fuel_for(1));
+        state.update(&fuel::level(), &TurtleActionReturn::Number(2));
+        state.update(&go::forward(), &TurtleActionReturn::Success);
+        state.update(&turn::left(), &TurtleActionReturn::Success);
+        state.update(&go::up(), &TurtleActionReturn::Failure(FailureReason::MovementObstructed));
+        assert_eq!(FuelLevel::Known(1), state.level);
+        assert_eq!(Some(true), state.has_fuel_for(1));
+        assert_eq!(Some(false), state.has_fuel_for(2));
+    }
+
+    #[test]
+    fn refuel_and_limits() {
+        let mut state = FuelState::new();
+        state.update(&go::forward(), &TurtleActionReturn::Failure(FailureReason::OutOfFuel));
+        assert_eq!(FuelLevel::Known(0), state.level);
+        state.update(&fuel::limit(), &TurtleActionReturn::Number(20000));
+        assert_eq!(Some(20000), state.space());
+        state.update(&fuel::refuel(), &TurtleActionReturn::Success);
+        assert_eq!(FuelLevel::Unknown, state.level);
+        assert_eq!(None, state.space());
+        state.update(&fuel::level(), &TurtleActionReturn::Unlimited);
+        state.update(&go::forward(), &TurtleActionReturn::Success);
+        assert_eq!(FuelLevel::Unlimited, state.level);
+        assert_eq!(Some(true), state.has_fuel_for(1_000_000));
+    }
+}
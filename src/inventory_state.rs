@@ -0,0 +1,525 @@
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::turtle_action::{FailureReason, TurtleAction, TurtleActionReturn};
+use crate::turtle_rotation::RelativeDirection;
+
+pub const SLOT_COUNT: u8 = 16;
+pub const DEFAULT_STACK_SIZE: u32 = 64;
+
+/// Stack size of an item by its name, for the common items which don't stack to 64
+pub fn stack_size(name: &str) -> u32 {
+    let unstackable = ["_pickaxe", "_axe", "_shovel", "_sword", "_hoe", "_bucket"];
+    match name {
+        "minecraft:ender_pearl"|"minecraft:egg"|"minecraft:snowball"|"minecraft:bucket"|"minecraft:oak_sign" => 16,
+        x if unstackable.iter().any(|suffix| x.ends_with(suffix)) => 1,
+        _ => DEFAULT_STACK_SIZE
+    }
+}
+
+/// The item a block drops when dug with a pickaxe, for the common blocks which don't drop themselves
+pub fn dropped_item(block: &str) -> &str {
+    match block {
+        "minecraft:stone" => "minecraft:cobblestone",
+        "minecraft:grass_block" => "minecraft:dirt",
+        "minecraft:coal_ore" => "minecraft:coal",
+        "minecraft:diamond_ore" => "minecraft:diamond",
+        "minecraft:emerald_ore" => "minecraft:emerald",
+        "minecraft:lapis_ore" => "minecraft:lapis_lazuli",
+        "minecraft:redstone_ore" => "minecraft:redstone",
+        x => x
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Confidence {
+    Unknown,
+    /// Inferred from the effects of actions, e.g. a dig that is assumed to add what the mined block drops
+    Guessed,
+    /// Reported by the turtle itself
+    Observed
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Slot {
+    pub name: Option<String>, // None if the slot is empty or the item is not known
+    pub count: Option<u32>, // None if not known
+    pub damage: Option<u32>,
+    pub max_count: Option<u32>, // stack size, only reported by detailed ItemDetail
+    pub confidence: Confidence
+}
+
+impl Slot {
+    pub fn unknown() -> Self {
+        Slot {name: None, count: None, damage: None, max_count: None, confidence: Confidence::Unknown}
+    }
+
+    pub fn empty(confidence: Confidence) -> Self {
+        Slot {name: None, count: Some(0), damage: None, max_count: None, confidence}
+    }
+
+    pub fn item(name: &str, count: u32, confidence: Confidence) -> Self {
+        Slot {name: Some(name.to_string()), count: Some(count), damage: None, max_count: None, confidence}
+    }
+
+    pub fn from_detail(detail: &Map<String, Value>) -> Self {
+        Slot {
+            name: detail.get("name").and_then(Value::as_str).map(str::to_string),
+            count: detail.get("count").and_then(Value::as_u64).map(|x| x as u32),
+            damage: detail.get("damage").and_then(Value::as_u64).map(|x| x as u32),
+            max_count: detail.get("maxCount").and_then(Value::as_u64).map(|x| x as u32),
+            confidence: Confidence::Observed
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == Some(0)
+    }
+
+    fn can_stack(&self, name: Option<&str>) -> bool {
+        match (self.count, name) {
+            (Some(count), Some(name)) => {
+                count > 0 && count < self.stack_size() && self.name.as_deref() == Some(name)
+            },
+            _ => false
+        }
+    }
+
+    /// Adds items to the slot, `count` is None when the amount is not known.
+    fn add(&mut self, name: Option<&str>, count: Option<u32>) {
+        if self.is_empty() {
+            self.name = name.map(str::to_string);
+            self.damage = None;
+            self.max_count = None;
+        }
+        self.count = match (self.count, count) {
+            (Some(x), Some(count)) => Some(x + count),
+            _ => None
+        };
+        self.confidence = Confidence::Guessed;
+    }
+
+    /// Takes up to `count` items from a slot with known contents, returning the item and how many were taken.
+    pub fn take(&mut self, count: u32) -> Option<(String, u32)> {
+        let (name, available) = match (&self.name, self.count) {
+            (Some(name), Some(available)) if available > 0 => (name.clone(), available),
+            _ => return None
+        };
+        let taken = available.min(count);
+        if taken == available {
+            *self = Slot::empty(self.confidence);
+        } else {
+            self.count = Some(available - taken);
+        }
+        Some((name, taken))
+    }
+
+    /// Reported stack size if known, otherwise guessed from the name
+    pub fn stack_size(&self) -> u32 {
+        self.max_count.unwrap_or_else(|| self.name.as_deref().map(stack_size).unwrap_or(DEFAULT_STACK_SIZE))
+    }
+
+    /// Items that still fit in the slot, None if not known.
+    pub fn space(&self) -> Option<u32> {
+        match self.count {
+            Some(0) => Some(DEFAULT_STACK_SIZE),
+            Some(count) => Some(self.stack_size().saturating_sub(count)),
+            None => None
+        }
+    }
+
+    /// How many of the named item still fit in the slot, None if not known.
+    pub fn space_for(&self, name: &str) -> Option<u32> {
+        match self.count {
+            Some(0) => Some(stack_size(name)),
+            Some(_) if self.name.as_deref() == Some(name) => self.space(),
+            Some(_) if self.name.is_some() => Some(0),
+            _ => None
+        }
+    }
+
+    fn remove(&mut self, count: u32) {
+        match self.count {
+            Some(x) if x <= count => *self = Slot::empty(Confidence::Guessed),
+            Some(x) => {
+                self.count = Some(x - count);
+                self.confidence = Confidence::Guessed;
+            },
+            None => *self = Slot::unknown()
+        }
+    }
+}
+
+/// Stores items into `slots`, first onto matching stacks and then into empty slots, both in the
+/// given order of indices. Returns how many didn't fit.
+pub fn store(slots: &mut [Slot], order: &[usize], name: &str, mut count: u32) -> u32 {
+    while count > 0 {
+        let target = order.iter().find(|i| slots[**i].can_stack(Some(name)))
+            .or_else(|| order.iter().find(|i| slots[**i].is_empty()))
+            .cloned();
+        let slot = match target {
+            Some(slot) => &mut slots[slot],
+            None => break
+        };
+        let moved = count.min(slot.space_for(name).unwrap_or(0));
+        slot.add(Some(name), Some(moved));
+        slot.confidence = Confidence::Observed;
+        count -= moved;
+    }
+    count
+}
+
+/// How many of the item still fit into `slots`, counting only slots with known contents
+pub fn space_for(slots: &[Slot], name: &str) -> u32 {
+    slots.iter().map(|slot| slot.space_for(name).unwrap_or(0)).sum()
+}
+
+/// Guesses the contents of the turtle's 16 inventory slots. Queries such as ItemDetail and
+/// ItemCount are taken as the truth, other actions update the slots by what they usually do.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InventoryState {
+    pub slots: Vec<Slot>, // slot n is at index n-1
+    pub selected: Option<u8>, // [1, 16]
+    pub left: Slot, // equipped upgrades, e.g. a pickaxe or a modem
+    pub right: Slot,
+    #[serde(skip)]
+    last_inspected: Option<(RelativeDirection, String)>
+}
+
+impl Default for InventoryState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InventoryState {
+    pub fn new() -> Self {
+        InventoryState {
+            slots: vec![Slot::unknown(); SLOT_COUNT as usize],
+            selected: None,
+            left: Slot::unknown(),
+            right: Slot::unknown(),
+            last_inspected: None
+        }
+    }
+
+    /// An inventory known to be empty with the first slot selected, as on a freshly placed turtle.
+    pub fn empty() -> Self {
+        InventoryState {
+            slots: vec![Slot::empty(Confidence::Observed); SLOT_COUNT as usize],
+            selected: Some(1),
+            left: Slot::empty(Confidence::Observed),
+            right: Slot::empty(Confidence::Observed),
+            last_inspected: None
+        }
+    }
+
+    pub fn slot(&self, slot: u8) -> &Slot {
+        &self.slots[slot as usize - 1]
+    }
+
+    fn slot_mut(&mut self, slot: u8) -> &mut Slot {
+        &mut self.slots[slot as usize - 1]
+    }
+
+    pub fn selected_slot(&self) -> Option<&Slot> {
+        self.selected.map(|x| self.slot(x))
+    }
+
+    /// Slots which are known to contain the named item
+    pub fn find(&self, name: &str) -> Vec<u8> {
+        (1..=SLOT_COUNT).filter(|i| self.slot(*i).name.as_deref() == Some(name) && !self.slot(*i).is_empty()).collect()
+    }
+
+    /// Known number of the named item, ignoring slots whose count is unknown
+    pub fn count_of(&self, name: &str) -> u32 {
+        self.find(name).iter().filter_map(|i| self.slot(*i).count).sum()
+    }
+
+    pub fn empty_slots(&self) -> Vec<u8> {
+        (1..=SLOT_COUNT).filter(|i| self.slot(*i).is_empty()).collect()
+    }
+
+    /// Picks up items the way the turtle does: first the selected slot, then a matching stack,
+    /// then the first empty slot after the selected one.
+    fn pick_up(&mut self, name: Option<&str>, count: Option<u32>) {
+        let start = self.selected.unwrap_or(1);
+        let order: Vec<u8> = (0..SLOT_COUNT).map(|i| (start - 1 + i) % SLOT_COUNT + 1).collect();
+        let target = order.iter().find(|i| self.slot(**i).can_stack(name))
+            .or_else(|| order.iter().find(|i| self.slot(**i).is_empty()))
+            .cloned();
+        match target {
+            Some(slot) => self.slot_mut(slot).add(name, count),
+            None => {
+                // Either the inventory is full, or the item went to a slot we know nothing about
+                for slot in order {
+                    if self.slot(slot).count.is_none() {
+                        *self.slot_mut(slot) = Slot::unknown();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Slot indices starting from the selected slot, the order in which the turtle stores items
+    fn store_order(&self) -> Vec<usize> {
+        let start = self.selected.unwrap_or(1) as usize - 1;
+        (0..SLOT_COUNT as usize).map(|i| (start + i) % SLOT_COUNT as usize).collect()
+    }
+
+    /// Stores items into slots with known contents the way the turtle does, returns how many didn't fit.
+    /// Used by the simulator, whose inventory is always fully known.
+    pub fn insert(&mut self, name: &str, count: u32) -> u32 {
+        let order = self.store_order();
+        store(&mut self.slots, &order, name, count)
+    }
+
+    /// How many of the item still fit, counting only slots with known contents
+    pub fn space_for(&self, name: &str) -> u32 {
+        space_for(&self.slots, name)
+    }
+
+    fn remove_selected(&mut self, count: Option<u32>) {
+        if let Some(selected) = self.selected {
+            self.slot_mut(selected).remove(count.unwrap_or(u32::MAX));
+        }
+    }
+
+    pub fn update(&mut self, action: &TurtleAction, result: &TurtleActionReturn) {
+        match (action, result) {
+            (TurtleAction::Select{slot}, TurtleActionReturn::Boolean(true)) => {
+                self.selected = Some(*slot);
+            },
+            (TurtleAction::GetSelectedSlot, TurtleActionReturn::Number(slot)) if (1..=SLOT_COUNT as u32).contains(slot) => {
+                self.selected = Some(*slot as u8);
+            },
+            (TurtleAction::ItemDetail{slot, ..}, TurtleActionReturn::DetailSuccess(detail)) => {
+                *self.slot_mut(*slot) = Slot::from_detail(detail);
+            },
+            (TurtleAction::ItemDetail{slot, ..}, TurtleActionReturn::Failure(FailureReason::SlotIsEmpty)) => {
+                *self.slot_mut(*slot) = Slot::empty(Confidence::Observed);
+            },
+            (TurtleAction::ItemCount{slot}, TurtleActionReturn::Number(0)) => {
+                *self.slot_mut(*slot) = Slot::empty(Confidence::Observed);
+            },
+            (TurtleAction::ItemCount{slot}, TurtleActionReturn::Number(count)) => {
+                let slot = self.slot_mut(*slot);
+                if slot.is_empty() {
+                    *slot = Slot::unknown();
+                }
+                slot.count = Some(*count);
+            },
+            (TurtleAction::Inspect{direction}, TurtleActionReturn::InspectSuccess(name, _)) => {
+                self.last_inspected = Some((*direction, name.clone()));
+            },
+            (TurtleAction::Dig{direction}, TurtleActionReturn::Success) => {
+                // The dropped item is known only if we just inspected the block
+                let name = match &self.last_inspected {
+                    Some((inspected, name)) if inspected == direction => Some(dropped_item(name).to_string()),
+                    _ => None
+                };
+                self.pick_up(name.as_deref(), Some(1));
+            },
+            (TurtleAction::Suck{count, ..}, TurtleActionReturn::Success) => {
+                // At least one item was taken, but only a count of one tells exactly how many
+                let taken = if *count == Some(1) {Some(1)} else {None};
+                self.pick_up(None, taken);
+            },
+            (TurtleAction::Place{..}, TurtleActionReturn::Success) => {
+                self.remove_selected(Some(1));
+            },
+            (TurtleAction::Drop{count, ..}, TurtleActionReturn::Success) => {
+                self.remove_selected(*count);
+            },
+            (TurtleAction::Refuel{count}, TurtleActionReturn::Success) => {
+                self.remove_selected(*count);
+            },
+            (TurtleAction::Drop{..}, TurtleActionReturn::Failure(FailureReason::NoItemsToDrop))|
+            (TurtleAction::Place{..}, TurtleActionReturn::Failure(FailureReason::NoItemsToPlace))|
+            (TurtleAction::Refuel{..}, TurtleActionReturn::Failure(FailureReason::NoItemsToCombust)) => {
+                if let Some(selected) = self.selected {
+                    *self.slot_mut(selected) = Slot::empty(Confidence::Observed);
+                }
+            },
+            (TurtleAction::Equip{direction}, TurtleActionReturn::Success) => {
+                // The selected item and the equipped upgrade swap places
+                if let Some(selected) = self.selected {
+                    let equipped = match direction {
+                        RelativeDirection::Left => &mut self.left,
+                        _ => &mut self.right
+                    };
+                    let previous = std::mem::replace(equipped, Slot::unknown());
+                    let new = self.slots[selected as usize - 1].clone();
+                    *equipped = match new.count {
+                        Some(0) => Slot::empty(Confidence::Guessed),
+                        Some(_) => Slot {count: Some(1), confidence: Confidence::Guessed, ..new},
+                        None => Slot::unknown()
+                    };
+                    self.slots[selected as usize - 1] = match previous.count {
+                        Some(_) => Slot {confidence: Confidence::Guessed, ..previous},
+                        None => Slot::unknown()
+                    };
+                }
+            },
+            (TurtleAction::Craft{..}, TurtleActionReturn::Success) => {
+                // Ingredients are consumed and the result may end up in any free slot
+                for slot in self.slots.iter_mut() {
+                    *slot = Slot::unknown();
+                }
+            },
+            (TurtleAction::TransferTo{slot, count}, TurtleActionReturn::Success) => {
+                if let Some(selected) = self.selected {
+                    let source = self.slot(selected).clone();
+                    if *slot != selected && !source.is_empty() {
+                        let moved = match (source.count, self.slot(*slot).space()) {
+                            (Some(n), Some(space)) => Some(n.min(space).min(count.unwrap_or(u32::MAX))),
+                            _ => None
+                        };
+                        match moved {
+                            Some(moved) => {
+                                self.slot_mut(*slot).add(source.name.as_deref(), Some(moved));
+                                self.remove_selected(Some(moved));
+                            },
+                            None => {
+                                *self.slot_mut(*slot) = Slot::unknown();
+                                *self.slot_mut(selected) = Slot::unknown();
+                            }
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+        if matches!(action, TurtleAction::Move{..}|TurtleAction::Turn{..}) {
+            self.last_inspected = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::turtle_action::{dig, drop, equip, go, inspect, inventory, place, suck};
+
+    fn detail(name: &str, count: u32) -> TurtleActionReturn {
+        let value = json!({"name": name, "count": count, "damage": 0});
+        TurtleActionReturn::DetailSuccess(value.as_object().unwrap().to_owned())
+    }
+
+    fn observe_empty(state: &mut InventoryState) {
+        for slot in 1..=SLOT_COUNT {
+            state.update(&inventory::count(slot), &TurtleActionReturn::Number(0));
+        }
+    }
+
+    #[test]
+    fn observed_details() {
+        let mut state = InventoryState::new();
+        assert_eq!(&Slot::unknown(), state.slot(3));
+        state.update(&inventory::detail(3), &detail("minecraft:cobblestone", 12));
+        state.update(&inventory::detail(4), &TurtleActionReturn::Failure(FailureReason::SlotIsEmpty));
+        assert_eq!(Some(12), state.slot(3).count);
+        assert_eq!(Some(0), state.slot(3).damage);
+        assert_eq!(Confidence::Observed, state.slot(3).confidence);
+        assert!(state.slot(4).is_empty());
+        assert_eq!(vec![3], state.find("minecraft:cobblestone"));
+        state.update(&inventory::count(3), &TurtleActionReturn::Number(5));
+        assert_eq!(5, state.count_of("minecraft:cobblestone"));
+    }
+
+    #[test]
+    fn dig_adds_inspected_block() {
+        let mut state = InventoryState::new();
+        observe_empty(&mut state);
+        state.update(&inventory::select(2), &TurtleActionReturn::Boolean(true));
+        state.update(&inspect::forward(), &TurtleActionReturn::InspectSuccess("minecraft:dirt".to_string(), Map::new()));
+        state.update(&dig::forward(), &TurtleActionReturn::Success);
+        state.update(&dig::up(), &TurtleActionReturn::Success);
+        assert_eq!(&Slot::item("minecraft:dirt", 1, Confidence::Guessed), state.slot(2));
+        assert_eq!(Some(1), state.slot(3).count);
+        assert_eq!(None, state.slot(3).name);
+        state.update(&go::forward(), &TurtleActionReturn::Success);
+        state.update(&dig::forward(), &TurtleActionReturn::Success);
+        assert_eq!(Some(1), state.slot(4).count);
+        state.update(&inspect::down(), &TurtleActionReturn::InspectSuccess("minecraft:stone".to_string(), Map::new()));
+        state.update(&dig::down(), &TurtleActionReturn::Success);
+        assert_eq!(&Slot::item("minecraft:cobblestone", 1, Confidence::Guessed), state.slot(5));
+    }
+
+    #[test]
+    fn place_and_drop_consume_selected() {
+        let mut state = InventoryState::new();
+        state.update(&inventory::select(1), &TurtleActionReturn::Boolean(true));
+        state.update(&inventory::detail(1), &detail("minecraft:torch", 2));
+        state.update(&place::forward(), &TurtleActionReturn::Success);
+        assert_eq!(Some(1), state.slot(1).count);
+        assert_eq!(Confidence::Guessed, state.slot(1).confidence);
+        state.update(&drop::forward(), &TurtleActionReturn::Success);
+        assert!(state.slot(1).is_empty());
+        state.update(&inventory::detail(1), &detail("minecraft:torch", 2));
+        state.update(&place::up(), &TurtleActionReturn::Failure(FailureReason::NoItemsToPlace));
+        assert_eq!(&Slot::empty(Confidence::Observed), state.slot(1));
+    }
+
+    #[test]
+    fn equip_swaps_with_selected() {
+        let mut state = InventoryState::new();
+        state.update(&inventory::selected(), &TurtleActionReturn::Number(4));
+        state.update(&inventory::detail(4), &detail("minecraft:diamond_pickaxe", 1));
+        state.update(&equip::left(), &TurtleActionReturn::Success);
+        assert_eq!(Some("minecraft:diamond_pickaxe".to_string()), state.left.name);
+        assert_eq!(Slot::unknown(), *state.slot(4));
+        state.update(&inventory::detail(4), &detail("computercraft:wireless_modem_normal", 1));
+        state.update(&equip::left(), &TurtleActionReturn::Success);
+        assert_eq!(Some("computercraft:wireless_modem_normal".to_string()), state.left.name);
+        assert_eq!(Some("minecraft:diamond_pickaxe".to_string()), state.slot(4).name);
+        assert_eq!(Confidence::Guessed, state.slot(4).confidence);
+    }
+
+    #[test]
+    fn stack_size_from_detailed_detail() {
+        let mut state = InventoryState::new();
+        observe_empty(&mut state);
+        state.update(&inventory::select(1), &TurtleActionReturn::Boolean(true));
+        let value = json!({"name": "minecraft:egg", "count": 16, "maxCount": 16});
+        state.update(&inventory::detail_full(1), &TurtleActionReturn::DetailSuccess(value.as_object().unwrap().to_owned()));
+        state.update(&inspect::down(), &TurtleActionReturn::InspectSuccess("minecraft:egg".to_string(), Map::new()));
+        state.update(&dig::down(), &TurtleActionReturn::Success);
+        assert_eq!(Some(16), state.slot(1).count);
+        assert_eq!(Some(1), state.slot(2).count);
+        state.update(&inventory::craft(), &TurtleActionReturn::Success);
+        assert_eq!(Slot::unknown(), *state.slot(2));
+    }
+
+    #[test]
+    fn transfer_moves_stack() {
+        let mut state = InventoryState::new();
+        observe_empty(&mut state);
+        state.update(&inventory::select(1), &TurtleActionReturn::Boolean(true));
+        state.update(&inventory::detail(1), &detail("minecraft:coal", 10));
+        state.update(&inventory::transfer_to(5), &TurtleActionReturn::Success);
+        assert!(state.slot(1).is_empty());
+        assert_eq!(&Slot::item("minecraft:coal", 10, Confidence::Guessed), state.slot(5));
+    }
+
+    #[test]
+    fn counted_drop_suck_and_transfer() {
+        let mut state = InventoryState::new();
+        observe_empty(&mut state);
+        state.update(&inventory::select(1), &TurtleActionReturn::Boolean(true));
+        state.update(&inventory::detail(1), &detail("minecraft:coal", 10));
+        state.update(&drop::up_count(3), &TurtleActionReturn::Success);
+        assert_eq!(Some(7), state.slot(1).count);
+        state.update(&inventory::transfer_to_count(2, 5), &TurtleActionReturn::Success);
+        assert_eq!(Some(2), state.slot(1).count);
+        assert_eq!(&Slot::item("minecraft:coal", 5, Confidence::Guessed), state.slot(2));
+        state.update(&inventory::detail(3), &detail("minecraft:coal", 62));
+        state.update(&inventory::transfer_to_count(3, 5), &TurtleActionReturn::Success);
+        assert!(state.slot(1).is_empty());
+        assert_eq!(Some(64), state.slot(3).count);
+        state.update(&suck::forward_count(1), &TurtleActionReturn::Success);
+        assert_eq!(Some(1), state.slot(1).count);
+        state.update(&suck::forward(), &TurtleActionReturn::Success);
+        assert_eq!(None, state.slot(4).count);
+    }
+}
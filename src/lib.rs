@@ -1,10 +1,19 @@
 pub mod turtle_action;
 pub mod turtle_state;
+pub mod block_palette;
 pub mod turtle_program;
 pub mod turtle_rotation;
 pub mod vec3;
 pub mod turtle;
 pub mod pathfind;
 pub mod location_state;
+pub mod fuel_state;
+pub mod inventory_state;
 pub mod world_simulator;
-pub mod run_history;
\ No newline at end of file
+pub mod multi_runner;
+pub mod faults;
+pub mod scenario;
+pub mod world_generator;
+pub mod run_history;
+pub mod config;
+pub mod protocol;
\ No newline at end of file
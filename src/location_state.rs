@@ -1,28 +1,39 @@
 use std::ops::Index;
 
+use anyhow::{anyhow, Result};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::report;
 use crate::turtle_action::{TurtleAction, TurtleActionReturn};
 use crate::turtle_rotation::{AxisDirection, get_dest_axisdirection, RelativeDirection, Rotation};
 use crate::turtle_state::Coord;
 use crate::vec3::Vec3;
 
 //  Two different measurements guarantee the orientation of the state
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LocationMode {
     Relative(Option<(Coord, Coord)>), // relative pos1, absolute pos1
     Absolute((Coord, Rotation)) // difference of new relative pos2 and relative pos1, and same for absolute position
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LocationState {
     pub loc: Coord, // Relative location
     pub loc_absolute: Option<Coord>, // Absolute, requires two GPS measurements from different locations
     pub direction: AxisDirection,
     pub direction_absolute: AxisDirection,
     pub location_precision: LocationMode,
+    #[serde(skip)] // recorded separately by the run history
     pub history: Vec<(Coord, AxisDirection)>
 }
 
+impl Default for LocationState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LocationState {
     pub const DEFAULT_DIRECTION: AxisDirection = AxisDirection::Xp;
     pub fn new() -> Self {
@@ -36,19 +47,19 @@ impl LocationState {
         }
     }
 
-    fn update_gps(&mut self, new_absolute: &Vec3<i32>) {
-        println!("Updating gps {:?}", self.location_precision);
+    fn update_gps(&mut self, new_absolute: &Vec3<i32>) -> Result<()> {
+        report!(Debug, "Updating gps {:?}", self.location_precision);
         match &self.location_precision {
             LocationMode::Relative(None) => {
                 self.location_precision = LocationMode::Relative(Some((self.loc.clone(), new_absolute.clone())));
             },
             LocationMode::Relative(Some(old_loc)) => {
                 let (old_rel, old_abs) = old_loc;
-                let rel_diff = &self.loc-&old_rel; // cur relative - old relative
+                let rel_diff = &self.loc-old_rel; // cur relative - old relative
                 if rel_diff.0 == 0 && rel_diff.2 == 0 { // Can't determine rotation with no x or z offsets
-                    return;
+                    return Ok(());
                 }
-                let abs_diff = new_absolute-&old_abs; // cur absolute - old absolute
+                let abs_diff = new_absolute-old_abs; // cur absolute - old absolute
                 let rotation = Rotation::find_rotation(&rel_diff, &abs_diff);
                 let new_offset = old_abs - &rotation.apply_to(old_rel);
                 // println!("Found rotation {:?} and offset {:?} from {:?} arriving at {:?}", rotation, new_offset, self.loc, new_absolute);
@@ -58,7 +69,7 @@ impl LocationState {
 
                 let mut new_history = vec![];
                 for (rel_coord, rel_dir) in &self.history {
-                    let loc_wrot = rotation.apply_to(&rel_coord);
+                    let loc_wrot = rotation.apply_to(rel_coord);
                     let loc_woffset = &loc_wrot + &new_offset;
                     let axis_rotated = AxisDirection::from(&rotation.apply_to(&rel_dir.to_unit_vector()));
                     new_history.push((loc_woffset, axis_rotated));
@@ -68,15 +79,15 @@ impl LocationState {
 
             },
             LocationMode::Absolute(_) => {
-                if self.loc_absolute.as_ref().unwrap() != new_absolute {
-                    panic!("New gps measurement {:?} differs from calculated value of {:?}", new_absolute, self.loc_absolute);
+                if self.loc_absolute.as_ref() != Some(new_absolute) {
+                    return Err(anyhow!("New gps measurement {:?} differs from calculated value of {:?}", new_absolute, self.loc_absolute));
                 } else {
                     // println!("GPS {:?} == {:?}", new_absolute, self.loc_absolute.as_ref().unwrap());
                 }
             }
         }
-        println!("Update done {:?}", self.location_precision);
-
+        report!(Debug, "Update done {:?}", self.location_precision);
+        Ok(())
     }
 
     fn update_absolute_location(&mut self) {
@@ -85,12 +96,12 @@ impl LocationState {
             let loc_wrot = rot.apply_to(&self.loc);
             let loc_woffset = &loc_wrot + base;
             self.loc_absolute = Some(loc_woffset.clone());
-            println!("Rotating {:?}, woffset {:?}", self.loc, loc_woffset);
+            report!(Debug, "Rotating {:?}, woffset {:?}", self.loc, loc_woffset);
             let unit = self.direction.to_unit_vector();
             let unit_rotated = rot.apply_to(&unit);
             let as_axis_dir = AxisDirection::from(&unit_rotated);
 
-            println!("{:?} {:?} {:?}", unit, unit_rotated, as_axis_dir);
+            report!(Debug, "{:?} {:?} {:?}", unit, unit_rotated, as_axis_dir);
             self.direction_absolute = as_axis_dir;
             let latest = (loc_woffset, self.direction_absolute.clone());
             if Some(&latest) != self.history.last() {
@@ -121,8 +132,7 @@ impl LocationState {
         let history = self.get_path_absolute();
 
         let mut result = vec![];
-        for i in 0..history.len() {
-            let (movement_dir, rot) = &history[i];
+        for (i, (movement_dir, rot)) in history.iter().enumerate() {
             let (_position, axis_dir) = &self.history[i];
             // println!("{:?} {:?} {:?}", _position, axis_dir, axis_dir.to_unit_vector());
             let c = match (movement_dir, rot) {
@@ -167,53 +177,49 @@ impl LocationState {
         if self.loc_absolute.is_none() {
             None
         } else {
-            println!("GetDestDirAbsolute {:?} {:?}", self.direction_absolute, move_direction);
+            report!(Debug, "GetDestDirAbsolute {:?} {:?}", self.direction_absolute, move_direction);
             let unit_dir = get_dest_axisdirection(&self.direction_absolute, move_direction);
             Some(unit_dir)
         }
     }
 
     pub fn get_dest_position_absolute(&self, move_direction: &RelativeDirection) -> Option<Coord> {
-        if self.loc_absolute.is_none() {
-            None
-        } else {
-            Some(self.loc_absolute.as_ref().unwrap() + self.get_dest_direction_absolute(move_direction).as_ref().unwrap())
-        }
+        let loc_absolute = self.loc_absolute.as_ref()?;
+        Some(loc_absolute + &self.get_dest_direction_absolute(move_direction)?)
     }
 
 
 
-    pub fn update(&mut self, action: &TurtleAction, result: &TurtleActionReturn) {
+    pub fn update(&mut self, action: &TurtleAction, result: &TurtleActionReturn) -> Result<()> {
         match action {
             TurtleAction::Move {direction} => {
                 if *result != TurtleActionReturn::Success {
-                    return;
+                    return Ok(());
                 }
-                let unit_dir = self.get_dest_direction_local(&direction);
+                let unit_dir = self.get_dest_direction_local(direction);
                 self.loc += &unit_dir;
 
             },
             TurtleAction::Turn {direction} => {
                 if *result != TurtleActionReturn::Success {
-                    return;
+                    return Ok(());
                 }
                 let new_dir = match direction {
                     RelativeDirection::Left => self.direction.rotate_left(),
                     RelativeDirection::Right => self.direction.rotate_right(),
-                    _ => panic!()
+                    _ => return Err(anyhow!("Unsupported turn direction {:?}", direction))
                 };
                 self.direction = new_dir;
             },
             TurtleAction::GpsLocate{..} => {
-                if let TurtleActionReturn::Coordinate(location) = &*result {
-                    self.update_gps(location);
+                if let TurtleActionReturn::Coordinate(location) = result {
+                    self.update_gps(location)?;
                 }
             }
-            TurtleAction::Detect{..} => {}, // Does not affect movement
-            TurtleAction::Inspect{..} => {},
-            _ => todo!("Not implemented: {:?}", action)
+            _ => {} // Does not affect location
         }
         self.update_absolute_location();
+        Ok(())
     }
 
 
@@ -221,7 +227,7 @@ impl LocationState {
 
 impl Index<usize> for LocationState {
     type Output = i32;
-    fn index<'a>(&'a self, i: usize) -> &'a i32 {
+    fn index(&self, i: usize) -> &i32 {
         &self.loc[i]
     }
 }
@@ -233,7 +239,7 @@ impl Index<usize> for LocationState {
 mod tests {
 
     use super::*;
-    use crate::turtle_action::{turn, go, gps};
+    use crate::turtle_action::{turn, go, gps, dig, FailureReason};
     use crate::world_simulator::Runner;
     use crate::turtle_program::InitGpsProgram;
 
@@ -243,29 +249,29 @@ mod tests {
 
         assert_eq!(AxisDirection::Xp, state.direction);
         let move_action = go::forward();
-        state.update(&move_action, &TurtleActionReturn::Success);
+        state.update(&move_action, &TurtleActionReturn::Success).unwrap();
         assert_eq!(AxisDirection::Xp, state.direction);
         assert_eq!(Coord::new(1,0,0), state.loc);
-        state.update(&go::backward(), &TurtleActionReturn::Success);
+        state.update(&go::backward(), &TurtleActionReturn::Success).unwrap();
         assert_eq!(Coord::zero(), state.loc);
-        state.update(&go::up(), &TurtleActionReturn::Success);
+        state.update(&go::up(), &TurtleActionReturn::Success).unwrap();
         assert_eq!(Coord::new(0,1,0), state.loc);
-        state.update(&go::down(), &TurtleActionReturn::Success);
+        state.update(&go::down(), &TurtleActionReturn::Success).unwrap();
         assert_eq!(Coord::zero(), state.loc);
     }
     #[test]
     fn test_turn_move() {
         let mut state = LocationState::new();
-        state.update(&turn::left(), &TurtleActionReturn::Success);
+        state.update(&turn::left(), &TurtleActionReturn::Success).unwrap();
         assert_eq!(AxisDirection::Zm, state.direction);
-        state.update(&go::forward(), &TurtleActionReturn::Success);
+        state.update(&go::forward(), &TurtleActionReturn::Success).unwrap();
         assert_eq!(AxisDirection::AD_ZM, state.loc);
-        state.update(&go::backward(), &TurtleActionReturn::Success);
+        state.update(&go::backward(), &TurtleActionReturn::Success).unwrap();
         assert_eq!(Coord::zero(), state.loc);
-        state.update(&turn::right(), &TurtleActionReturn::Success);
-        state.update(&turn::right(), &TurtleActionReturn::Success);
+        state.update(&turn::right(), &TurtleActionReturn::Success).unwrap();
+        state.update(&turn::right(), &TurtleActionReturn::Success).unwrap();
         assert_eq!(AxisDirection::Zp, state.direction);
-        state.update(&go::forward(), &TurtleActionReturn::Success);
+        state.update(&go::forward(), &TurtleActionReturn::Success).unwrap();
         assert_eq!(AxisDirection::AD_ZP, state.loc);
     }
 
@@ -277,21 +283,33 @@ mod tests {
                 34                      23
         */
         for _ in 0..4 {
-            state.update(&turn::left(), &TurtleActionReturn::Success);
-            state.update(&go::forward(), &TurtleActionReturn::Success);
+            state.update(&turn::left(), &TurtleActionReturn::Success).unwrap();
+            state.update(&go::forward(), &TurtleActionReturn::Success).unwrap();
         }
         assert_eq!(AxisDirection::Xp, state.direction);
         assert_eq!(Coord::zero(), state.loc);
 
         for _ in 0..4 {
-            state.update(&turn::right(), &TurtleActionReturn::Success);
-            state.update(&go::forward(), &TurtleActionReturn::Success);
+            state.update(&turn::right(), &TurtleActionReturn::Success).unwrap();
+            state.update(&go::forward(), &TurtleActionReturn::Success).unwrap();
         }
         assert_eq!(AxisDirection::Xp, state.direction);
         assert_eq!(Coord::zero(), state.loc);
 
     }
 
+    #[test]
+    fn test_non_movement_actions_and_gps_mismatch() {
+        let mut state = LocationState::new();
+        state.update(&dig::forward(), &TurtleActionReturn::Failure(FailureReason::Other("Odd".to_string()))).unwrap();
+        assert_eq!(Coord::zero(), state.loc);
+        state.update(&gps::locate(), &TurtleActionReturn::Coordinate(Coord::new(5, 0, 5))).unwrap();
+        state.update(&go::forward(), &TurtleActionReturn::Success).unwrap();
+        state.update(&gps::locate(), &TurtleActionReturn::Coordinate(Coord::new(6, 0, 5))).unwrap();
+        assert!(state.update(&gps::locate(), &TurtleActionReturn::Coordinate(Coord::new(9, 0, 9))).is_err());
+        assert!(state.update(&TurtleAction::Turn {direction: RelativeDirection::Up}, &TurtleActionReturn::Success).is_err());
+    }
+
     #[test]
     fn test_gps() {
         let mut runner = Runner::make_world_unknown_loc_unknown("test_box", Coord::new(0,0,0), AxisDirection::Zm);
@@ -302,12 +320,12 @@ mod tests {
             assert_eq!(&Coord::zero(), offset);
             assert_eq!(&Rotation::Y270, rotation);
         } else {
-            assert!(false);
+            panic!("Expected absolute location");
         }
         runner.execute_action(&gps::locate());
         runner.execute_action(&gps::locate());
         assert_eq!(AxisDirection::Zm, runner.shadow_location().direction_absolute);
-        runner.run(Box::new(gps_program));
+        assert!(runner.run(Box::new(gps_program)).is_finished());
         runner.location().print_history();
         println!("{:?}", runner.location().history);
         assert_eq!(2, runner.location().get_path_absolute().len());
@@ -315,7 +333,7 @@ mod tests {
             assert_eq!(&Coord::zero(), offset);
             assert_eq!(&Rotation::Y270, rotation);
         } else {
-            assert!(false);
+            panic!("Expected absolute location");
         }
         assert_eq!(AxisDirection::Zm, runner.location().direction_absolute);
 
@@ -330,7 +348,7 @@ mod tests {
         let mut runner = Runner::make_world_unknown_loc_unknown("test_box", Coord::new(0,0,0), AxisDirection::Zm);
         let gps_program = InitGpsProgram::new();
         assert_eq!(0, runner.location().get_path_absolute().len());
-        runner.run(Box::new(gps_program));
+        assert!(runner.run(Box::new(gps_program)).is_finished());
         runner.location().print_history();
         assert_eq!(2, runner.location().get_path_absolute().len());
         let history = runner.location().get_path_absolute();
@@ -2,16 +2,15 @@
 use tungstenite::WebSocket;
 use anyhow::{anyhow, Result};
 use serde_json::{self};
-use serde_derive::{Deserialize, Serialize};
-use turtlers::turtle_action::*;
 use turtlers::turtle_program::*;
 use turtlers::turtle::*;
-use turtlers::vec3::Vec3;
-use std::{net::{TcpListener, TcpStream}, thread::{self, spawn}, time};
+use turtlers::protocol::*;
+use std::{collections::HashSet, net::{TcpListener, TcpStream}, sync::{Arc, Mutex}, thread::{self, spawn}, time};
 
 use tungstenite::{accept, handshake::HandshakeRole, HandshakeError, Message};
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
 use tungstenite as tung;
-use turtlers::turtle_state::StateSerializationPolicy;
+use turtlers::config::{HelpRequested, ServerConfig, Verbosity};
 
 fn must_not_block<Role: HandshakeRole>(err: HandshakeError<Role>) -> tung::Error {
     match err {
@@ -21,174 +20,143 @@ fn must_not_block<Role: HandshakeRole>(err: HandshakeError<Role>) -> tung::Error
 }
 
 
-#[derive(Serialize, Deserialize)]
-struct InitMsg {
-    id: String
+pub fn create_turtle(init: InitMsg, config: &ServerConfig) -> Result<Turtle> {
+    let ser_policy = config.state_policy(&init.id);
+    Turtle::new(init.id, ser_policy, &config.runs_dir)
 }
 
-
-
-#[derive(Serialize, Deserialize)]
-struct UnknownMsg {
-    msgtype: String
-}
-
-pub fn create_turtle(initialization_msg: &str) -> Result<Turtle> {
-    let v: InitMsg = serde_json::from_str(initialization_msg)?;
-    let ser_policy = StateSerializationPolicy::LoadAndSave {load_dir: "state".to_string(), save_dir: "state".to_string()};
-    let turtle = Turtle::new(v.id, ser_policy);
-    Ok(turtle)
-}
-
-#[derive(Serialize)]
-struct TurtleResult {
-    result: String
-}
-
-fn turtle_ok(socket: &mut WebSocket<TcpStream>) -> Result<()> {
-    let value = TurtleResult{result:"ok".to_string()};
-    let contents = serde_json::to_string(&value)?;
+fn send(socket: &mut WebSocket<TcpStream>, msg: &ServerMsg) -> Result<()> {
+    let contents = serde_json::to_string(msg)?;
     socket.write_message(Message::Text(contents))?;
     Ok(())
 }
 
-
-fn parse_response(turtle: &Turtle, response: &TurtleResponseMsg) -> Result<TurtleActionReturn> {
-    let last_action = turtle.last_action.as_ref().unwrap(); // If None, our program is initializing out of order
-    let result = &response.result;
-
-    match last_action {
-        TurtleAction::Move{..}|
-        TurtleAction::Turn{..}|
-        TurtleAction::Place{..}|
-        TurtleAction::Dig{..}|
-        TurtleAction::Attack{..}|
-        TurtleAction::Suck{..}|
-        TurtleAction::Drop{..}|
-        TurtleAction::TransferTo{..} => {
-            let success = result["1"].as_bool().unwrap();
-            if success {
-                Ok(TurtleActionReturn::Success)
-            } else {
-                let reason = parse_failure_reason(result["2"].as_str().unwrap());
-
-                Ok(TurtleActionReturn::Failure(reason))
-            }
-        },
-        TurtleAction::Inspect{..} => {
-            let success = result["1"].as_bool().unwrap();
-            if success {
-                let inspect_result = &result["2"];
-                let block_name = inspect_result["name"].to_string();
-                let state_table = inspect_result["state"].as_object();
-                let final_table = match state_table {
-                    Some(x) => x.to_owned(),
-                    None => serde_json::Map::new()
-                };
-
-                Ok(TurtleActionReturn::InspectSuccess(block_name, final_table))
-
-            } else {
-                let reason = parse_failure_reason(result["2"].as_str().unwrap());
-                Ok(TurtleActionReturn::Failure(reason))
-            }
-        },
-        TurtleAction::ItemDetail{..} => {
-            let detail_result = result["1"].as_object();
-            match detail_result {
-                Some(x) => Ok(TurtleActionReturn::DetailSuccess(x.to_owned())),
-                None => Ok(TurtleActionReturn::Failure(FailureReason::SlotIsEmpty))
-            }
-        },
-        TurtleAction::Detect{..}|
-        TurtleAction::Compare{..}|
-        TurtleAction::Select{..}|
-        TurtleAction::CompareTo{..} => {
-            let is_block = result["1"].as_bool().unwrap();
-            Ok(TurtleActionReturn::Boolean(is_block))
-        },
-        TurtleAction::ItemCount{..}|
-        TurtleAction::ItemSpace{..} => {
-            let num = result["1"].as_u64().unwrap();
-            Ok(TurtleActionReturn::Number(num as u32))
-        },
-        TurtleAction::GpsLocate{timeout_ms: _, debug} => {
-            if *debug {
-                panic!();
-            } else {
-                if result["n"].as_u64().unwrap() == 1 {
-                    Ok(TurtleActionReturn::Failure(FailureReason::GpsLocateFailure))
-                } else {
-                    let x = result["1"].as_i64().unwrap();
-                    let y = result["2"].as_i64().unwrap();
-                    let z = result["3"].as_i64().unwrap();
-                    Ok(TurtleActionReturn::Coordinate(Vec3::<i32>(x as i32, y as i32, z as i32)))
-                }
-            }
-        }
-
-        _ => panic!()
-    }
-
-}
-
-
-fn execute_message(turtle: &mut Turtle, msg: &str) -> Result<()> { // later, be more specific
-
-    let msg_wtype: UnknownMsg = serde_json::from_str(msg)?;
-
-    match msg_wtype.msgtype.as_str() {
-        "start" => {
-            println!("{}", msg);
-            let create_program_msg: StartProgramMsg = serde_json::from_str(msg)?;
+fn execute_message(turtle: &mut Turtle, msg: &str) -> Result<()> {
+    match ClientMsg::parse(msg)? {
+        ClientMsg::Start(create_program_msg) => {
             let program = create_program(&create_program_msg)?;
             turtle.set_program(program);
         },
-        "response" => {
-            let resp_msg: TurtleResponseMsg = serde_json::from_str(&msg)?;
-            let resp = parse_response(&turtle, &resp_msg)?;
-            turtle.update(&resp);
-            
-        },
-        x => return Err(anyhow!("Invalid msgtype received when program is finished: {}", x))
+        ClientMsg::Response {result} => {
+            let last_action = turtle.last_action.as_ref().ok_or(ProtocolError::NoPendingAction)?;
+            let resp = parse_response(last_action, &result)?;
+            turtle.update(&resp)?;
+        }
     };
     Ok(())
 }
 
 fn next_response(turtle: &mut Turtle) -> Result<String> {
-    let action = turtle.next()?;
-    let action_str = serde_json::to_string(&action.to_api_call())?;
+    let action = turtle.next_action()?;
+    let action_str = serde_json::to_string(&ServerMsg::Call(action.to_api_call()))?;
 
     Ok(action_str)
 }
 
-fn handle_client_loop(mut socket: &mut WebSocket<TcpStream>, initialization_msg: &str) -> Result<()> {
-    println!("Received initialization msg {}", initialization_msg);
-    let mut turtle = create_turtle(&initialization_msg)?;
-    println!("Successfully initialized turtle {}", turtle.id);
-    turtle_ok(&mut socket)?;
+/// Ids of the turtles that currently have an open connection. Two connections with the same id
+/// would share a state directory, so the second one is refused.
+type ConnectedTurtles = Arc<Mutex<HashSet<String>>>;
+
+/// Keeps the turtle id registered for as long as its connection is alive.
+struct Connection {
+    id: String,
+    connected: ConnectedTurtles,
+    verbosity: Verbosity
+}
+
+impl Connection {
+    fn register(id: &str, connected: &ConnectedTurtles, config: &ServerConfig) -> Result<Self> {
+        let mut ids = connected.lock().map_err(|_| anyhow!("Connection registry is poisoned"))?;
+        if !ids.insert(id.to_string()) {
+            return Err(anyhow!("Turtle {} is already connected", id));
+        }
+        if config.verbosity >= Verbosity::Normal {
+            println!("Turtle {} connected, {} turtle(s) online", id, ids.len());
+        }
+        Ok(Connection {id: id.to_string(), connected: connected.clone(), verbosity: config.verbosity})
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Ok(mut ids) = self.connected.lock() {
+            ids.remove(&self.id);
+            if self.verbosity >= Verbosity::Normal {
+                println!("Turtle {} disconnected, {} turtle(s) online", self.id, ids.len());
+            }
+        }
+    }
+}
+
+fn handle_client_loop(socket: &mut WebSocket<TcpStream>, initialization_msg: &str, connected: &ConnectedTurtles, config: &ServerConfig) -> Result<()> {
+    let debug = config.verbosity >= Verbosity::Debug;
+    if debug {
+        println!("Received initialization msg {}", initialization_msg);
+    }
+    let handshake = InitMsg::parse(initialization_msg)
+        .map_err(anyhow::Error::from)
+        .and_then(|init| {
+            let connection = Connection::register(&init.id, connected, config)?;
+            Ok((connection, create_turtle(init, config)?))
+        });
+    let (_connection, mut turtle) = match handshake {
+        Ok(x) => x,
+        Err(e) => {
+            send(socket, &ServerMsg::Handshake(HandshakeReply::error(&e.to_string())))?;
+            return Err(e);
+        }
+    };
+    send(socket, &ServerMsg::Handshake(HandshakeReply::ok()))?;
     loop {
-        match socket.read_message()? {
-            Message::Text(x) => {
-                execute_message(&mut turtle, &x.as_str())?;
-                let response = next_response(&mut turtle)?;
+        match socket.read_message() {
+            Ok(Message::Text(x)) => {
+                if debug {
+                    println!("{} -> {}", turtle.id, x);
+                }
+                // After a message we can't handle, the state may be only partly updated, so the
+                // program can't go on. The connection is closed and the error reported.
+                if let Err(e) = execute_message(&mut turtle, x.as_str()) {
+                    let reason = CloseFrame {code: CloseCode::Error, reason: "Could not handle the message".into()};
+                    socket.close(Some(reason))?;
+                    return Err(e.context(format!("Turtle {} sent a message that could not be handled", turtle.id)));
+                }
+                let response = match next_response(&mut turtle) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        let reason = CloseFrame {code: CloseCode::Error, reason: "The program failed".into()};
+                        socket.close(Some(reason))?;
+                        return Err(e.context(format!("Program of turtle {} failed", turtle.id)));
+                    }
+                };
+                if debug {
+                    println!("{} <- {}", turtle.id, response);
+                }
                 socket.write_message(Message::Text(response))?;
             },
-            Message::Ping(_) | Message::Pong(_) | Message::Close(_) => {
-                println!("Ping/pong/close")
+            Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => {
+                if debug {
+                    println!("Ping/pong")
+                }
+            },
+            Ok(Message::Close(_)) => {
+                // The close reply is flushed by the next read, which then reports ConnectionClosed
+                if debug {
+                    println!("Turtle {} is closing the connection", turtle.id)
+                }
             },
-            Message::Binary(_) => panic!("Binary message received!")
+            Ok(Message::Binary(_)) => return Err(anyhow!("Binary message received!")),
+            Err(tung::Error::ConnectionClosed) | Err(tung::Error::AlreadyClosed) => return Ok(()),
+            Err(e) => return Err(e.into())
         }
         thread::sleep(time::Duration::from_millis(1));
     }
 }
 
-fn accept_client(stream: TcpStream) -> Result<()> {
+fn accept_client(stream: TcpStream, connected: &ConnectedTurtles, config: &ServerConfig) -> Result<()> {
     let mut socket = accept(stream).map_err(must_not_block)?;
-    println!("Waiting for initialization");
     match socket.read_message()? {
         Message::Text(x) => {
-            handle_client_loop(&mut socket, x.as_str())
+            handle_client_loop(&mut socket, x.as_str(), connected, config)
         },
         _ => {
             Err(anyhow!("Invalid handshake"))
@@ -199,23 +167,46 @@ fn accept_client(stream: TcpStream) -> Result<()> {
 
 
 fn main() {
-    
-    let listener = TcpListener::bind("25.75.103.40:80").unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match ServerConfig::from_args(&args) {
+        Ok(config) => {
+            config.verbosity.set_current();
+            Arc::new(config)
+        },
+        Err(e) if e.is::<HelpRequested>() => {
+            println!("{}", e);
+            std::process::exit(0);
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let listener = match TcpListener::bind(&config.bind) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on {}: {}", config.bind, e);
+            std::process::exit(2);
+        }
+    };
+    if config.verbosity >= Verbosity::Normal {
+        println!("Listening on {}", config.bind);
+    }
+    let connected: ConnectedTurtles = Arc::new(Mutex::new(HashSet::new()));
 
     for stream in listener.incoming() {
-        let h = spawn(move || match stream {
+        match stream {
             Ok(stream) => {
-                if let Err(err) = accept_client(stream) {
-                    match err {
-                        e => println!("Client error: {}", e),
+                let connected = connected.clone();
+                let config = config.clone();
+                spawn(move || {
+                    let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                    if let Err(e) = accept_client(stream, &connected, &config) {
+                        println!("Client error ({}): {}", peer, e);
                     }
-                }
+                });
             },
             Err(ref e) => println!("Error accepting stream: {}", e),
-        });
-        match h.join() {
-            Ok(_) => {},
-            Err(_) => return
         }
     }
 }
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+use anyhow::Result;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use crate::faults::Fault;
+use crate::turtle::Turtle;
+use crate::turtle_action::{TurtleAction, TurtleActionReturn};
+use crate::turtle_program::TurtleProgram;
+use crate::turtle_rotation::AxisDirection;
+use crate::turtle_state::Coord;
+use crate::world_simulator::{Runner, ShadowTurtle, Step};
+
+/// In which order the turtles take their steps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepOrder {
+    RoundRobin,
+    Seeded(u64) // a random turtle for every step, the same for the same seed
+}
+
+/// Several turtles in the same simulated world. The turtles take the cells they stand in, so one
+/// obstructs the moves of another, and they can hand items to each other through chests.
+///
+/// The world is kept by a single `Runner`, which holds the turtle whose turn it is. The other
+/// turtles wait outside of it with their part of the shadow state.
+pub struct MultiRunner {
+    runner: Runner,
+    parked: Vec<Option<(Turtle, ShadowTurtle)>>, // None for the turtle in the runner
+    active: usize,
+    finished: Vec<bool>,
+    order: StepOrder,
+    rng: StdRng,
+    next: usize // next turtle of the round robin
+}
+
+impl MultiRunner {
+    /// The turtle of the runner becomes turtle 0
+    pub fn new(runner: Runner, order: StepOrder) -> Self {
+        let seed = match order {
+            StepOrder::Seeded(seed) => seed,
+            StepOrder::RoundRobin => 0
+        };
+        MultiRunner {
+            runner,
+            parked: vec![None],
+            active: 0,
+            finished: vec![false],
+            order,
+            rng: StdRng::seed_from_u64(seed),
+            next: 0
+        }
+    }
+
+    /// Adds a turtle with an empty inventory and unlimited fuel, returns its index.
+    /// `start_location_known` is whether the turtle knows where it starts, the simulator always does.
+    pub fn add_turtle(&mut self, id: &str, start_location: (Coord, AxisDirection), start_location_known: bool) -> usize {
+        assert!(self.locations().all(|loc| loc != start_location.0), "Two turtles at {:?}", start_location.0);
+        self.parked.push(Some(Runner::new_turtle(id, start_location, start_location_known)));
+        self.finished.push(false);
+        self.parked.len() - 1
+    }
+
+    pub fn turtle_count(&self) -> usize {
+        self.parked.len()
+    }
+
+    /// The runner with the given turtle in it, for setting it up and checking on it
+    pub fn runner(&mut self, turtle: usize) -> &mut Runner {
+        self.activate(turtle);
+        &mut self.runner
+    }
+
+    pub fn set_program(&mut self, turtle: usize, program: Box<dyn TurtleProgram>) {
+        self.runner(turtle).set_program(program);
+        self.finished[turtle] = false;
+    }
+
+    /// Where the turtle really is
+    pub fn location_of(&self, turtle: usize) -> Coord {
+        let location = match &self.parked[turtle] {
+            Some((_, shadow)) => &shadow.location,
+            None => self.runner.shadow_location()
+        };
+        location.loc_absolute.clone().expect("Shadow locations are always absolute")
+    }
+
+    fn locations(&self) -> impl Iterator<Item = Coord> + '_ {
+        (0..self.turtle_count()).map(move |turtle| self.location_of(turtle))
+    }
+
+    /// Executes a single action of the given turtle, with the other turtles in the way
+    pub fn execute_action(&mut self, turtle: usize, action: &TurtleAction) -> Result<TurtleActionReturn, Fault> {
+        self.runner(turtle).try_execute_action(action)
+    }
+
+    /// Executes the next action of the next turtle which hasn't stopped yet. None once all of them
+    /// have stopped. A turtle whose program fails stops with the error.
+    pub fn step(&mut self) -> Option<(usize, Result<Step>)> {
+        let turtle = self.next_turtle()?;
+        let next = self.runner(turtle).execute_next();
+        if matches!(next, Ok((TurtleAction::Stop, _))|Err(_)) {
+            self.finished[turtle] = true;
+        }
+        Some((turtle, next))
+    }
+
+    /// Runs the programs until every turtle has stopped, or until the program of one fails
+    pub fn run(&mut self) -> Result<()> {
+        while let Some((turtle, next)) = self.step() {
+            if let Err(err) = next {
+                return Err(err.context(format!("Program of turtle {} failed", turtle)));
+            }
+        }
+        Ok(())
+    }
+
+    fn next_turtle(&mut self) -> Option<usize> {
+        let waiting: Vec<usize> = (0..self.turtle_count()).filter(|turtle| !self.finished[*turtle]).collect();
+        match self.order {
+            StepOrder::Seeded(_) => waiting.choose(&mut self.rng).copied(),
+            StepOrder::RoundRobin => {
+                let turtle = *waiting.iter().find(|turtle| **turtle >= self.next).or_else(|| waiting.first())?;
+                self.next = turtle + 1;
+                Some(turtle)
+            }
+        }
+    }
+
+    /// Puts the turtle into the runner, and the others in its way
+    fn activate(&mut self, turtle: usize) {
+        if turtle != self.active {
+            let (mut next_turtle, mut shadow) = self.parked[turtle].take().unwrap();
+            self.runner.swap_turtle(&mut next_turtle, &mut shadow);
+            self.parked[self.active] = Some((next_turtle, shadow));
+            self.active = turtle;
+        }
+        let occupied: HashSet<Coord> = self.locations().enumerate()
+            .filter(|(other, _)| *other != turtle)
+            .map(|(_, loc)| loc)
+            .collect();
+        self.runner.set_occupied(occupied);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turtle_action::{detect, drop, go, suck, FailureReason};
+    use crate::turtle_program::{FromActionsProgram, MultiProgram};
+    use crate::turtle_state::Block;
+
+    /// Two turtles facing each other with a free cell between them
+    fn two_turtles(order: StepOrder) -> MultiRunner {
+        let runner = Runner::make_world_known_loc_known("box_w_wall", Coord::new(-1,0,1), AxisDirection::Xp);
+        let mut multi = MultiRunner::new(runner, order);
+        multi.add_turtle("multi_test_1", (Coord::new(1,0,1), AxisDirection::Xm), true);
+        multi
+    }
+
+    #[test]
+    fn turtles_obstruct_each_other() {
+        let mut multi = two_turtles(StepOrder::RoundRobin);
+        let middle = Coord::new(0,0,1);
+        assert_eq!(Ok(TurtleActionReturn::Success), multi.execute_action(0, &go::forward()));
+        assert_eq!(Ok(TurtleActionReturn::Failure(FailureReason::MovementObstructed)), multi.execute_action(1, &go::forward()));
+        assert_eq!(Block::Air, multi.runner(1).shadow_world().get(&middle));
+        assert_eq!(Ok(TurtleActionReturn::Success), multi.execute_action(0, &go::backward()));
+        assert_eq!(Ok(TurtleActionReturn::Success), multi.execute_action(1, &go::forward()));
+        assert_eq!(Coord::new(-1,0,1), multi.location_of(0));
+        assert_eq!(middle, multi.location_of(1));
+    }
+
+    #[test]
+    fn turtles_exchange_items_through_chests() {
+        let mut multi = two_turtles(StepOrder::RoundRobin);
+        let chest = Coord::new(0,0,1);
+        multi.runner(0).add_chest(chest.clone(), &[]);
+        multi.runner(0).add_items("minecraft:coal", 20);
+        multi.set_program(0, Box::new(FromActionsProgram::from(&[drop::forward()])));
+        multi.set_program(1, Box::new(FromActionsProgram::from(&[detect::forward(), suck::forward()])));
+        multi.run().unwrap();
+        assert_eq!(0, multi.runner(0).shadow_inventory().count_of("minecraft:coal"));
+        assert_eq!(20, multi.runner(1).shadow_inventory().count_of("minecraft:coal"));
+        assert_eq!(0, multi.runner(1).container(&chest).unwrap().count_of("minecraft:coal"));
+    }
+
+    #[test]
+    fn seeded_order_is_reproducible() {
+        let order_of = |seed| {
+            let mut multi = two_turtles(StepOrder::Seeded(seed));
+            multi.set_program(0, Box::new(FromActionsProgram::from(&[detect::forward(); 5])));
+            multi.set_program(1, Box::new(FromActionsProgram::from(&[detect::forward(); 5])));
+            std::iter::from_fn(|| multi.step().map(|(turtle, _)| turtle)).collect::<Vec<usize>>()
+        };
+        let order = order_of(1);
+        assert_eq!(12, order.len());
+        assert_eq!(order, order_of(1));
+        assert!((1..10).any(|seed| order_of(seed) != order));
+    }
+
+    #[test]
+    fn failing_program_stops_its_turtle() {
+        let mut multi = two_turtles(StepOrder::RoundRobin);
+        // Expects one more program than it gets, so it runs out of them on its first step
+        let mut program = MultiProgram::new(Box::new(FromActionsProgram::from(&[])));
+        program.add(Box::new(FromActionsProgram::from(&[])));
+        multi.set_program(0, Box::new(program));
+        multi.set_program(1, Box::new(FromActionsProgram::from(&[detect::forward(); 2])));
+        assert!(multi.run().is_err());
+        assert!(matches!(multi.step(), Some((1, Ok((TurtleAction::Detect {..}, _))))));
+    }
+}
@@ -1,5 +1,6 @@
 
 use std::{cmp::min, collections::{HashMap}};
+use crate::report;
 use crate::{turtle_action::{TurtleAction}, vec3::*};
 use crate::{turtle_rotation::*};
 use crate::{turtle_state::*};
@@ -62,7 +63,7 @@ fn generate_neighbors(node: &Node) -> Vec<(Node, TurtleAction)> {
         let loc_dir = get_dest_axisdirection(&node.dir, rel_dir);
         let loc = &node.loc + &loc_dir;
         result.push(
-            (Node{loc:loc, dir:node.dir.clone()},
+            (Node{loc, dir:node.dir.clone()},
             TurtleAction::Move{direction:rel_dir.to_owned()})
         );
     }
@@ -81,6 +82,7 @@ fn generate_neighbors(node: &Node) -> Vec<(Node, TurtleAction)> {
 //     // Turning = 1
 //     // Air=1 (have to take a step)
 //     // Block=2 if can_dig (have to dig and move = two steps), otherwise inf.
+    // Bedrock=inf
     // let neighbors = generate_neighbors(&current);
     // for neighbor in &neighbors {
     //     let new_dist = &end.loc-&neighbor.loc;
@@ -96,11 +98,12 @@ fn generate_neighbors(node: &Node) -> Vec<(Node, TurtleAction)> {
 // }
 
 fn dist_heuristic(state: &WorldState, start: &Node, end: &Node, can_dig: bool, cur_cost: u64) -> u64 {
-    let current = start.clone();
+    let current = start;
     let block = state.state.get(&current.loc).unwrap_or( &Block::Unknown);
-    if !can_dig && block == &Block::Block {
+    if block == &Block::Bedrock || block == &Block::Lava || (!can_dig && block == &Block::Block) {
          return 999999;
     }
+    let hazard_cost = if block == &Block::Water {2} else {0}; // water can be crossed, but is avoided
     // let mut rotation_needed = Rotation::find_rotation(&
     let rotation_needed = AxisDirection::dot(&start.dir, &end.dir);
     let distance_needed = &end.loc-&start.loc;
@@ -113,14 +116,14 @@ fn dist_heuristic(state: &WorldState, start: &Node, end: &Node, can_dig: bool, c
                 let d = Vec3(min(distance_needed.0.abs(), 1), 0, min(distance_needed.2.abs(), 1));
                 
                 if d == Vec3::zero() ||  d == end.dir.to_unit_vector() || d == -end.dir.to_unit_vector() {
-                    println!("0-cost Y0, goal at: {:?} with distance remaining {:?} at direction {:?} end_dir: {:?}", end.dir, distance_needed, d, end.dir.to_unit_vector());
+                    report!(Debug, "0-cost Y0, goal at: {:?} with distance remaining {:?} at direction {:?} end_dir: {:?}", end.dir, distance_needed, d, end.dir.to_unit_vector());
                     0
                 } else {
-                    println!("NON0-cost Y0,  goal at: {:?} with distance remaining {:?} at direction {:?} end_dir: {:?}", end.dir, distance_needed, d, end.dir.to_unit_vector());
+                    report!(Debug, "NON0-cost Y0,  goal at: {:?} with distance remaining {:?} at direction {:?} end_dir: {:?}", end.dir, distance_needed, d, end.dir.to_unit_vector());
                     2
                 }
             } else {
-                println!("NON0-cost Y0 goal at: {:?} with distance remaining {:?} ", end.dir, distance_needed);
+                report!(Debug, "NON0-cost Y0 goal at: {:?} with distance remaining {:?} ", end.dir, distance_needed);
                 2 // we need to turn at least twice more 
             }
             
@@ -129,7 +132,7 @@ fn dist_heuristic(state: &WorldState, start: &Node, end: &Node, can_dig: bool, c
         Rotation::Y180 => 2
     }; // AT LEAST this amount of rotation. However:
 
-    let cost = (distance_needed.abs_sum() + rotation_cost) as u64;
+    let cost = (distance_needed.abs_sum() + rotation_cost + hazard_cost) as u64;
     cur_cost + cost
 }
 
@@ -175,8 +178,7 @@ fn blocking_path(state: &WorldState, start: &Node, end: &Node) -> u64 {
 pub struct RTAStar {
     h: HashMap<Node, u64>,
     goal: Node,
-    next: Option<TurtleAction>,
-    it: i32
+    next: Option<TurtleAction>
 }
 
 
@@ -185,30 +187,29 @@ impl RTAStar {
         let goal = Node{loc: goal_loc, dir: goal_dir};
         RTAStar {
             h: HashMap::new(),
-            goal: goal,
-            next: None,
-            it: 0
+            goal,
+            next: None
         }
     }
 
-    fn select_successor(&self, nodes: &Vec<(Node, TurtleAction)>, costs: &Vec<u64>) -> usize {
+    fn select_successor(&self, nodes: &[(Node, TurtleAction)], costs: &[u64]) -> usize {
         let min_cost = costs.iter().min().unwrap();
         let nodes_min_cost: Vec<usize> = (0..costs.len()).filter(|i| &costs[*i] == min_cost).collect();
-        let nodes_not_turning: Vec<usize> = nodes_min_cost.iter().map(|i| *i).filter(|i| matches!((&nodes[*i]).1, TurtleAction::Move{..})).collect();
-        if nodes_not_turning.len() > 0 {
+        let nodes_not_turning: Vec<usize> = nodes_min_cost.iter().copied().filter(|i| matches!(nodes[*i].1, TurtleAction::Move{..})).collect();
+        if !nodes_not_turning.is_empty() {
             nodes_not_turning[0]
         } else {
             nodes_min_cost[0]
         }
     }
 
-    fn get_second_cost(&self, costs: &Vec<u64>) -> u64 {
+    fn get_second_cost(&self, costs: &[u64]) -> u64 {
         let min_cost = costs.iter().min().unwrap();
         let nodes_min_cost: Vec<usize> = (0..costs.len()).filter(|i| &costs[*i] == min_cost).collect();
         if nodes_min_cost.len() > 1 {
             *min_cost
         } else {
-            let larger_than_min= costs.iter().filter(|f| *f > &min_cost).min();
+            let larger_than_min= costs.iter().filter(|f| *f > min_cost).min();
             *larger_than_min.unwrap_or(min_cost)
         }
     }
@@ -235,7 +236,7 @@ impl RTAStar {
                       +blocking_path(&state.world, &node.0, &self.goal)
                 }
             };
-            println!("{:?} {:?} {:?}", node.1, cost, self.h.get(&node.0).is_some());
+            report!(Debug, "{:?} {:?} {:?}", node.1, cost, self.h.contains_key(&node.0));
             costs.push(cost);
             
         }
@@ -254,15 +255,14 @@ impl RTAStar {
 
     pub fn update(&mut self, state: &TurtleState)  {
         if state.location.loc_absolute.is_some() {
-            let next = self.next_node(&state);
+            let next = self.next_node(state);
             self.next = Some(next);
         }
     }
 
     pub fn next(&self) -> Result<TurtleAction> {
-        let next = self.next.as_ref();
-        if next.is_some() {
-            Ok(next.unwrap().clone())
+        if let Some(next) = self.next {
+            Ok(next)
         } else {
             Err(anyhow!("No steps left in pathfinding!"))
         }
@@ -0,0 +1,521 @@
+use std::convert::TryFrom;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use crate::turtle_action::*;
+use crate::turtle_program::StartProgramMsg;
+use crate::turtle_rotation::RelativeDirection;
+use crate::vec3::Vec3;
+
+/*
+Every connection starts with the turtle sending an InitMsg, to which the server answers with a
+HandshakeReply. After a successful handshake the turtle sends ClientMsgs, and the server answers
+each one with the next TurtleApiCall to execute.
+
+Version 1 is the original protocol, whose InitMsg carries no version.
+Version 2 adds the version to both sides of the handshake and the error reply.
+ */
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error("Malformed message: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("Unsupported protocol version {0}, supported versions are {}..={}", MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)]
+    UnsupportedVersion(u32),
+    #[error("Received a response, but no action is waiting for one")]
+    NoPendingAction,
+    #[error("Did not expect a response to {0:?}")]
+    UnexpectedResponse(TurtleAction),
+    #[error("Response to {action:?} should have {expected} at index {index}, got {value}")]
+    UnexpectedValue {action: TurtleAction, index: usize, expected: &'static str, value: Value},
+    #[error("Unknown call {0:?}")]
+    UnknownCall(TurtleApiCall)
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// First message sent by the turtle.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InitMsg {
+    pub id: String,
+    #[serde(default = "legacy_version")]
+    pub version: u32
+}
+
+impl InitMsg {
+    pub fn new(id: &str) -> Self {
+        InitMsg {id: id.to_string(), version: PROTOCOL_VERSION}
+    }
+
+    pub fn parse(msg: &str) -> Result<Self, ProtocolError> {
+        let init: InitMsg = serde_json::from_str(msg)?;
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&init.version) {
+            return Err(ProtocolError::UnsupportedVersion(init.version));
+        }
+        Ok(init)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum HandshakeReply {
+    Ok {version: u32},
+    Error {version: u32, error: String}
+}
+
+impl HandshakeReply {
+    pub fn ok() -> Self {
+        HandshakeReply::Ok {version: PROTOCOL_VERSION}
+    }
+
+    pub fn error(error: &str) -> Self {
+        HandshakeReply::Error {version: PROTOCOL_VERSION, error: error.to_string()}
+    }
+}
+
+/// Argument of a turtle API call. Serialized as a plain JSON value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ApiArg {
+    Bool(bool),
+    Int(i64),
+    Number(f64),
+    Text(String)
+}
+
+/// A single Lua function call the turtle should execute, e.g. `turtle.select(2)`.
+/// Missing arguments are sent as null, which the turtle passes on as nil.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TurtleApiCall {
+    pub cmd: String,
+    pub arg1: Option<ApiArg>,
+    pub arg2: Option<ApiArg>
+}
+
+impl TurtleApiCall {
+    pub(crate) fn new(cmd: &str) -> Self {
+        TurtleApiCall{cmd: cmd.to_string(), arg1: None, arg2: None}
+    }
+
+    pub(crate) fn new_wargs(cmd: &str, arg1: Option<ApiArg>, arg2: Option<ApiArg>) -> Self {
+        TurtleApiCall{cmd: cmd.to_string(), arg1, arg2}
+    }
+}
+
+/// Any message the server sends to the turtle.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ServerMsg {
+    Handshake(HandshakeReply),
+    Call(TurtleApiCall)
+}
+
+/// Return values of a Lua call packed with `table.pack`, e.g. `{"n":2,"1":false,"2":"Movement obstructed"}`.
+/// Nil values are absent from the table.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct PackedReturn(Map<String, Value>);
+
+impl PackedReturn {
+    pub fn new(values: Vec<Value>) -> Self {
+        let mut table = Map::new();
+        table.insert("n".to_string(), Value::from(values.len()));
+        for (i, value) in values.into_iter().enumerate() {
+            if !value.is_null() {
+                table.insert((i + 1).to_string(), value);
+            }
+        }
+        PackedReturn(table)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.get("n").and_then(Value::as_u64).unwrap_or(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value at the 1-based Lua index, Null if it was nil.
+    pub fn get(&self, index: usize) -> &Value {
+        self.0.get(&index.to_string()).unwrap_or(&Value::Null)
+    }
+
+    fn expect<'a, T>(&'a self, action: &TurtleAction, index: usize, expected: &'static str,
+                     convert: impl Fn(&'a Value) -> Option<T>) -> Result<T, ProtocolError> {
+        let value = self.get(index);
+        convert(value).ok_or_else(|| ProtocolError::UnexpectedValue {
+            action: *action, index, expected, value: value.clone()
+        })
+    }
+}
+
+/// A number that fits into u32, larger ones are malformed rather than truncated
+fn as_u32(value: &Value) -> Option<u32> {
+    value.as_u64().and_then(|num| u32::try_from(num).ok())
+}
+
+fn as_i32(value: &Value) -> Option<i32> {
+    value.as_i64().and_then(|num| i32::try_from(num).ok())
+}
+
+/// Messages sent by the turtle after the handshake, tagged by `msgtype`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "msgtype", rename_all = "snake_case")]
+pub enum ClientMsg {
+    /// Starts a program, e.g. `{"msgtype":"start","args":["initgps"]}`
+    Start(StartProgramMsg),
+    /// Return values of the previous TurtleApiCall
+    Response {result: PackedReturn}
+}
+
+impl ClientMsg {
+    pub fn parse(msg: &str) -> Result<Self, ProtocolError> {
+        Ok(serde_json::from_str(msg)?)
+    }
+}
+
+fn success_or_failure(action: &TurtleAction, result: &PackedReturn,
+                      on_success: impl FnOnce() -> Result<TurtleActionReturn, ProtocolError>) -> Result<TurtleActionReturn, ProtocolError> {
+    if result.expect(action, 1, "a boolean", Value::as_bool)? {
+        on_success()
+    } else {
+        let reason = match result.get(2) {
+            Value::Null => FailureReason::Unspecified,
+            _ => parse_failure_reason(result.expect(action, 2, "a failure reason", Value::as_str)?)
+        };
+        Ok(TurtleActionReturn::Failure(reason))
+    }
+}
+
+/// Interprets the return values of `action` sent by the turtle.
+pub fn parse_response(action: &TurtleAction, result: &PackedReturn) -> Result<TurtleActionReturn, ProtocolError> {
+    match action {
+        TurtleAction::Move{..}|
+        TurtleAction::Turn{..}|
+        TurtleAction::Place{..}|
+        TurtleAction::Dig{..}|
+        TurtleAction::Attack{..}|
+        TurtleAction::Suck{..}|
+        TurtleAction::Drop{..}|
+        TurtleAction::TransferTo{..}|
+        TurtleAction::Refuel{..}|
+        TurtleAction::Equip{..}|
+        TurtleAction::Craft{..} => success_or_failure(action, result, || Ok(TurtleActionReturn::Success)),
+        TurtleAction::Inspect{..} => success_or_failure(action, result, || {
+            let inspect_result = result.expect(action, 2, "a block table", Value::as_object)?;
+            let block_name = inspect_result.get("name").and_then(Value::as_str).unwrap_or_default().to_string();
+            let state_table = inspect_result.get("state").and_then(Value::as_object).cloned().unwrap_or_default();
+            Ok(TurtleActionReturn::InspectSuccess(block_name, state_table))
+        }),
+        TurtleAction::ItemDetail{..} => {
+            match result.get(1) {
+                Value::Object(x) => Ok(TurtleActionReturn::DetailSuccess(x.to_owned())),
+                Value::Null => Ok(TurtleActionReturn::Failure(FailureReason::SlotIsEmpty)),
+                value => Err(ProtocolError::UnexpectedValue {
+                    action: *action, index: 1, expected: "an item table or nil", value: value.clone()
+                })
+            }
+        },
+        TurtleAction::Detect{..}|
+        TurtleAction::Compare{..}|
+        TurtleAction::Select{..}|
+        TurtleAction::CompareTo{..} => {
+            let is_block = result.expect(action, 1, "a boolean", Value::as_bool)?;
+            Ok(TurtleActionReturn::Boolean(is_block))
+        },
+        TurtleAction::ItemCount{..}|
+        TurtleAction::ItemSpace{..}|
+        TurtleAction::GetSelectedSlot => {
+            let num = result.expect(action, 1, "a number", as_u32)?;
+            Ok(TurtleActionReturn::Number(num))
+        },
+        TurtleAction::GpsLocate{..} => {
+            // Debug mode only prints to the turtle's terminal, the return values are the same
+            if result.get(1).is_null() {
+                Ok(TurtleActionReturn::Failure(FailureReason::GpsLocateFailure))
+            } else {
+                let x = result.expect(action, 1, "an integer", as_i32)?;
+                let y = result.expect(action, 2, "an integer", as_i32)?;
+                let z = result.expect(action, 3, "an integer", as_i32)?;
+                Ok(TurtleActionReturn::Coordinate(Vec3::<i32>(x, y, z)))
+            }
+        }
+        TurtleAction::GetFuelLevel|
+        TurtleAction::GetFuelLimit => {
+            match result.get(1) {
+                Value::String(x) if x == "unlimited" => Ok(TurtleActionReturn::Unlimited),
+                _ => {
+                    let num = result.expect(action, 1, "a number or \"unlimited\"", as_u32)?;
+                    Ok(TurtleActionReturn::Number(num))
+                }
+            }
+        },
+        TurtleAction::Stop => Err(ProtocolError::UnexpectedResponse(*action))
+    }
+}
+
+/// Reads the action back from the call, the other way around from `TurtleAction::to_api_call`.
+/// Used by clients that simulate a turtle.
+pub fn parse_call(call: &TurtleApiCall) -> Result<TurtleAction, ProtocolError> {
+    let unknown = || ProtocolError::UnknownCall(call.clone());
+    let int = |arg: &Option<ApiArg>| match arg {
+        Some(ApiArg::Int(x)) if *x >= 0 => Ok(Some(*x as u32)),
+        None => Ok(None),
+        _ => Err(unknown())
+    };
+    let slot = || int(&call.arg1)?.filter(|slot| (1..=16).contains(slot)).map(|slot| slot as u8).ok_or_else(unknown);
+    let flag = |arg: &Option<ApiArg>| match arg {
+        Some(ApiArg::Bool(x)) => *x,
+        _ => false
+    };
+    let (name, direction) = match call.cmd.strip_prefix("turtle.") {
+        Some(name) if name.ends_with("Up") => (&name[..name.len() - 2], RelativeDirection::Up),
+        Some(name) if name.ends_with("Down") => (&name[..name.len() - 4], RelativeDirection::Down),
+        Some(name) => (name, RelativeDirection::Forward),
+        None => (call.cmd.as_str(), RelativeDirection::Forward)
+    };
+    let action = match name {
+        "turnRight" => TurtleAction::Turn {direction: RelativeDirection::Right},
+        "turnLeft" => TurtleAction::Turn {direction: RelativeDirection::Left},
+        "forward" | "up" | "down" => {
+            let direction = match name {
+                "up" => RelativeDirection::Up,
+                "down" => RelativeDirection::Down,
+                _ => RelativeDirection::Forward
+            };
+            TurtleAction::Move {direction}
+        },
+        "back" => TurtleAction::Move {direction: RelativeDirection::Backward},
+        "dig" => TurtleAction::Dig {direction},
+        "detect" => TurtleAction::Detect {direction},
+        "place" => TurtleAction::Place {direction},
+        "drop" => TurtleAction::Drop {direction, count: int(&call.arg1)?},
+        "attack" => TurtleAction::Attack {direction},
+        "suck" => TurtleAction::Suck {direction, count: int(&call.arg1)?},
+        "inspect" => TurtleAction::Inspect {direction},
+        "compare" => TurtleAction::Compare {direction},
+        "select" => TurtleAction::Select {slot: slot()?},
+        "getItemCount" => TurtleAction::ItemCount {slot: slot()?},
+        "getItemSpace" => TurtleAction::ItemSpace {slot: slot()?},
+        "getItemDetail" => TurtleAction::ItemDetail {slot: slot()?, detailed: flag(&call.arg2)},
+        "transferTo" => TurtleAction::TransferTo {slot: slot()?, count: int(&call.arg2)?},
+        "compareTo" => TurtleAction::CompareTo {slot: slot()?},
+        "refuel" => TurtleAction::Refuel {count: int(&call.arg1)?},
+        "getFuelLevel" => TurtleAction::GetFuelLevel,
+        "getFuelLimit" => TurtleAction::GetFuelLimit,
+        "equipLeft" => TurtleAction::Equip {direction: RelativeDirection::Left},
+        "equipRight" => TurtleAction::Equip {direction: RelativeDirection::Right},
+        "craft" => TurtleAction::Craft {limit: int(&call.arg1)?},
+        "getSelectedSlot" => TurtleAction::GetSelectedSlot,
+        "gps.locate" => {
+            let timeout_ms = match call.arg1 {
+                Some(ApiArg::Number(seconds)) => (seconds * 1000.0) as u32,
+                Some(ApiArg::Int(seconds)) => (seconds * 1000) as u32,
+                _ => return Err(unknown())
+            };
+            TurtleAction::GpsLocate {timeout_ms, debug: flag(&call.arg2)}
+        },
+        "stop" => TurtleAction::Stop,
+        _ => return Err(unknown())
+    };
+    // Only the three direction calls take the Up and Down suffixes
+    if direction != RelativeDirection::Forward && !matches!(action, TurtleAction::Dig {..}|TurtleAction::Detect {..}|
+            TurtleAction::Place {..}|TurtleAction::Drop {..}|TurtleAction::Attack {..}|TurtleAction::Suck {..}|
+            TurtleAction::Inspect {..}|TurtleAction::Compare {..}) {
+        return Err(unknown());
+    }
+    Ok(action)
+}
+
+/// Packs the response like the turtle does, the other way around from `parse_response`
+pub fn pack_response(response: &TurtleActionReturn) -> PackedReturn {
+    let values = match response {
+        TurtleActionReturn::Success => vec![Value::Bool(true)],
+        TurtleActionReturn::Failure(FailureReason::GpsLocateFailure) |
+        TurtleActionReturn::Failure(FailureReason::SlotIsEmpty) => vec![Value::Null],
+        TurtleActionReturn::Failure(reason) => match reason.message() {
+            Some(message) => vec![Value::Bool(false), Value::from(message)],
+            None => vec![Value::Bool(false)]
+        },
+        TurtleActionReturn::InspectSuccess(name, state) => {
+            let mut block = Map::new();
+            block.insert("name".to_string(), Value::from(name.as_str()));
+            block.insert("state".to_string(), Value::Object(state.clone()));
+            vec![Value::Bool(true), Value::Object(block)]
+        },
+        TurtleActionReturn::DetailSuccess(detail) => vec![Value::Object(detail.clone())],
+        TurtleActionReturn::Boolean(x) => vec![Value::Bool(*x)],
+        TurtleActionReturn::Number(x) => vec![Value::from(*x)],
+        TurtleActionReturn::Coordinate(Vec3(x, y, z)) => vec![Value::from(*x), Value::from(*y), Value::from(*z)],
+        TurtleActionReturn::Unlimited => vec![Value::from("unlimited")]
+    };
+    PackedReturn::new(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response(msg: &str) -> PackedReturn {
+        match ClientMsg::parse(msg).unwrap() {
+            ClientMsg::Response {result} => result,
+            x => panic!("Expected a response, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn handshake_versions() {
+        assert_eq!(1, InitMsg::parse(r#"{"id":"3"}"#).unwrap().version);
+        assert_eq!(2, InitMsg::parse(r#"{"id":"3","version":2}"#).unwrap().version);
+        assert!(matches!(InitMsg::parse(r#"{"id":"3","version":99}"#), Err(ProtocolError::UnsupportedVersion(99))));
+        assert!(matches!(InitMsg::parse(r#"{"version":2}"#), Err(ProtocolError::Malformed(_))));
+        assert_eq!(r#"{"result":"ok","version":2}"#, serde_json::to_string(&HandshakeReply::ok()).unwrap());
+    }
+
+    #[test]
+    fn client_messages() {
+        let msg = ClientMsg::parse(r#"{"msgtype":"start","args":["initgps"]}"#).unwrap();
+        assert!(matches!(msg, ClientMsg::Start(_)));
+        assert!(ClientMsg::parse(r#"{"msgtype":"unknown"}"#).is_err());
+        assert!(ClientMsg::parse("not json").is_err());
+    }
+
+    #[test]
+    fn api_call_wire_format() {
+        let call = serde_json::to_value(inventory::select(3).to_api_call()).unwrap();
+        assert_eq!(json!({"cmd": "turtle.select", "arg1": 3, "arg2": null}), call);
+        let call = serde_json::to_value(gps::locate().to_api_call()).unwrap();
+        assert_eq!(json!({"cmd": "gps.locate", "arg1": 2.0, "arg2": false}), call);
+        let parsed: ServerMsg = serde_json::from_value(call).unwrap();
+        assert_eq!(ServerMsg::Call(gps::locate().to_api_call()), parsed);
+    }
+
+    #[test]
+    fn parse_move_responses() {
+        let result = response(r#"{"msgtype":"response","result":{"n":2,"1":false,"2":"Movement obstructed"}}"#);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::MovementObstructed), parse_response(&go::forward(), &result).unwrap());
+        let result = response(r#"{"msgtype":"response","result":{"n":1,"1":true}}"#);
+        assert_eq!(TurtleActionReturn::Success, parse_response(&go::forward(), &result).unwrap());
+    }
+
+    #[test]
+    fn parse_inspect_response() {
+        let result = PackedReturn::new(vec![json!(true), json!({"name": "minecraft:stone", "state": {"axis": "y"}})]);
+        match parse_response(&inspect::forward(), &result).unwrap() {
+            TurtleActionReturn::InspectSuccess(name, state) => {
+                assert_eq!("minecraft:stone", name);
+                assert_eq!(Some(&json!("y")), state.get("axis"));
+            },
+            x => panic!("Unexpected {:?}", x)
+        }
+    }
+
+    #[test]
+    fn unknown_failure_reasons() {
+        let result = PackedReturn::new(vec![json!(false), json!("Out of fuel")]);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::OutOfFuel), parse_response(&go::up(), &result).unwrap());
+        let result = PackedReturn::new(vec![json!(false), json!("Vibes are off")]);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::Other("Vibes are off".to_string())), parse_response(&go::up(), &result).unwrap());
+        let result = PackedReturn::new(vec![json!(false)]);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::Unspecified), parse_response(&inventory::transfer_to(2), &result).unwrap());
+    }
+
+    #[test]
+    fn parse_fuel_responses() {
+        let result = PackedReturn::new(vec![json!(1520)]);
+        assert_eq!(TurtleActionReturn::Number(1520), parse_response(&fuel::level(), &result).unwrap());
+        let result = PackedReturn::new(vec![json!("unlimited")]);
+        assert_eq!(TurtleActionReturn::Unlimited, parse_response(&fuel::limit(), &result).unwrap());
+        let result = PackedReturn::new(vec![json!(false), json!("Items not combustible")]);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::ItemsNotCombustible), parse_response(&fuel::refuel(), &result).unwrap());
+        assert_eq!(json!({"cmd": "turtle.refuel", "arg1": 4, "arg2": null}), serde_json::to_value(fuel::refuel_count(4).to_api_call()).unwrap());
+    }
+
+    #[test]
+    fn parse_equip_and_craft_responses() {
+        let result = PackedReturn::new(vec![json!(false), json!("Not a valid upgrade")]);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NotAValidUpgrade), parse_response(&equip::left(), &result).unwrap());
+        let result = PackedReturn::new(vec![json!(true)]);
+        assert_eq!(TurtleActionReturn::Success, parse_response(&inventory::craft_limit(1), &result).unwrap());
+        let result = PackedReturn::new(vec![json!(7)]);
+        assert_eq!(TurtleActionReturn::Number(7), parse_response(&inventory::selected(), &result).unwrap());
+        assert_eq!(json!({"cmd": "turtle.getItemDetail", "arg1": 2, "arg2": true}), serde_json::to_value(inventory::detail_full(2).to_api_call()).unwrap());
+        assert_eq!(json!({"cmd": "turtle.equipRight", "arg1": null, "arg2": null}), serde_json::to_value(equip::right().to_api_call()).unwrap());
+    }
+
+    #[test]
+    fn count_arguments() {
+        assert_eq!(json!({"cmd": "turtle.dropUp", "arg1": 3, "arg2": null}), serde_json::to_value(drop::up_count(3).to_api_call()).unwrap());
+        assert_eq!(json!({"cmd": "turtle.suck", "arg1": null, "arg2": null}), serde_json::to_value(suck::forward().to_api_call()).unwrap());
+        assert_eq!(json!({"cmd": "turtle.transferTo", "arg1": 5, "arg2": 10}), serde_json::to_value(inventory::transfer_to_count(5, 10).to_api_call()).unwrap());
+    }
+
+    #[test]
+    fn parse_gps_response() {
+        let result = PackedReturn::new(vec![json!(1), json!(-2), json!(3)]);
+        assert_eq!(TurtleActionReturn::Coordinate(Vec3(1, -2, 3)), parse_response(&gps::locate(), &result).unwrap());
+        let result = PackedReturn::new(vec![Value::Null]);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::GpsLocateFailure), parse_response(&gps::locate(), &result).unwrap());
+        let debug = TurtleAction::GpsLocate {timeout_ms: 1000, debug: true};
+        let result = PackedReturn::new(vec![json!(4), json!(5), json!(6)]);
+        assert_eq!(TurtleActionReturn::Coordinate(Vec3(4, 5, 6)), parse_response(&debug, &result).unwrap());
+    }
+
+    #[test]
+    fn calls_are_parsed_back() {
+        let actions = [go::forward(), go::backward(), go::up(), turn::left(), dig::down(), detect::up(), place::forward(),
+            drop::up_count(3), suck::forward(), attack::down(), inspect::up(), compare::forward(), inventory::select(3),
+            inventory::count(16), inventory::detail_full(2), inventory::transfer_to_count(5, 10), inventory::compare_to(4),
+            inventory::selected(), fuel::refuel_count(4), fuel::level(), fuel::limit(), equip::left(), inventory::craft_limit(1),
+            gps::locate(), TurtleAction::Stop];
+        for action in actions.iter() {
+            assert_eq!(action, &parse_call(&action.to_api_call()).unwrap());
+        }
+        let wire: TurtleApiCall = serde_json::from_value(serde_json::to_value(gps::locate().to_api_call()).unwrap()).unwrap();
+        assert_eq!(gps::locate(), parse_call(&wire).unwrap());
+        assert!(matches!(parse_call(&TurtleApiCall::new("turtle.turnRightUp")), Err(ProtocolError::UnknownCall(_))));
+        assert!(parse_call(&TurtleApiCall::new("turtle.select")).is_err());
+        assert!(parse_call(&TurtleApiCall::new("os.shutdown")).is_err());
+    }
+
+    #[test]
+    fn packed_responses_are_parsed_back() {
+        let responses = [
+            (go::forward(), TurtleActionReturn::Success),
+            (go::forward(), TurtleActionReturn::Failure(FailureReason::MovementObstructed)),
+            (inventory::transfer_to(2), TurtleActionReturn::Failure(FailureReason::Unspecified)),
+            (dig::forward(), TurtleActionReturn::Failure(FailureReason::Other("Vibes are off".to_string()))),
+            (inspect::forward(), TurtleActionReturn::InspectSuccess("minecraft:stone".to_string(), Map::new())),
+            (inventory::detail(1), TurtleActionReturn::Failure(FailureReason::SlotIsEmpty)),
+            (detect::forward(), TurtleActionReturn::Boolean(true)),
+            (inventory::count(1), TurtleActionReturn::Number(12)),
+            (gps::locate(), TurtleActionReturn::Coordinate(Vec3(1, -2, 3))),
+            (gps::locate(), TurtleActionReturn::Failure(FailureReason::GpsLocateFailure)),
+            (fuel::level(), TurtleActionReturn::Unlimited)
+        ];
+        for (action, response) in responses.iter() {
+            assert_eq!(response, &parse_response(action, &pack_response(response)).unwrap());
+        }
+    }
+
+    #[test]
+    fn malformed_responses_are_errors() {
+        let result = PackedReturn::new(vec![json!("yes")]);
+        assert!(matches!(parse_response(&go::forward(), &result), Err(ProtocolError::UnexpectedValue {index: 1, ..})));
+        let result = PackedReturn::new(vec![json!(false), json!(12)]);
+        assert!(matches!(parse_response(&dig::forward(), &result), Err(ProtocolError::UnexpectedValue {index: 2, ..})));
+        let result = PackedReturn::new(vec![json!(-1)]);
+        assert!(parse_response(&inventory::count(1), &result).is_err());
+        let result = PackedReturn::new(vec![json!(u64::from(u32::MAX) + 1)]);
+        assert!(matches!(parse_response(&fuel::level(), &result), Err(ProtocolError::UnexpectedValue {index: 1, ..})));
+        let result = PackedReturn::new(vec![json!(1), json!(i64::from(i32::MIN) - 1), json!(3)]);
+        assert!(matches!(parse_response(&gps::locate(), &result), Err(ProtocolError::UnexpectedValue {index: 2, ..})));
+        assert!(matches!(parse_response(&TurtleAction::Stop, &result), Err(ProtocolError::UnexpectedResponse(_))));
+    }
+}
@@ -1,6 +1,13 @@
+use std::fs::OpenOptions;
+use std::io::{prelude::*, ErrorKind};
 use std::time::{SystemTime, UNIX_EPOCH};
-use crate::turtle_state::{TurtleState};
-use crate::turtle_action::{TurtleAction};
+use anyhow::{Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use crate::inventory_state::InventoryState;
+use crate::location_state::LocationState;
+use crate::block_palette::BlockIdentity;
+use crate::turtle_state::{Coord, TurtleState, WorldDiff};
+use crate::turtle_action::{TurtleAction, TurtleActionReturn};
 
 
 pub fn create_run_id(id: &str) -> String {
@@ -9,50 +16,145 @@ pub fn create_run_id(id: &str) -> String {
     format!("{}_{}", id, duration.as_millis())
 }
 
+/// A single line of the run log. The first event of a run is always `Initial`, and replaying the
+/// `Action` events on top of it reproduces the state of the turtle at any point of the run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RunEvent {
+    Initial {
+        location: LocationState,
+        world: WorldDiff, // every known block
+        #[serde(default)] // not in runs recorded before block names were kept
+        names: Vec<(Coord, BlockIdentity)>,
+        inventory: InventoryState
+    },
+    Action {
+        action: TurtleAction,
+        result: TurtleActionReturn,
+        location: LocationState, // location after the action
+        world: WorldDiff // blocks changed by the action
+    }
+}
+
+/// Writes the run of a turtle to `<dir>/<run_id>.jsonl`, one `RunEvent` per line.
 pub struct RunHistory {
-    run_id: String,
     path: String
 }
 
-/*
-Things to record:
-Initial state of world
-Initial location
-Initial inventory
-
-Action that was carried out
-Result of the action
-
-Change of world in result of the action
-Change of location in result of the action
-
- */
-
-
 impl RunHistory {
-    pub fn new(id: String) -> Self {
-        let run_id = create_run_id(id.as_str());
-        std::fs::create_dir_all("runs").unwrap();
-
-        RunHistory {
-            run_id: run_id.clone(),
-            path: format!("runs/{}.txt", run_id)
+    pub const DEFAULT_DIR: &'static str = "runs";
+
+    pub fn new(id: String, dir: &str) -> Result<Self> {
+        let base_id = create_run_id(id.as_str());
+        std::fs::create_dir_all(dir).with_context(|| format!("Could not create runs directory {}", dir))?;
+
+        // Claim the file up front, so that runs of the same turtle started within the same millisecond don't mix
+        let mut run_id = base_id.clone();
+        for n in 1.. {
+            let path = format!("{}/{}.jsonl", dir, run_id);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(RunHistory {path}),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => run_id = format!("{}_{}", base_id, n),
+                Err(e) => return Err(e).with_context(|| format!("Could not create run history {}", path))
+            }
         }
+        unreachable!()
     }
 
-    pub fn add_initial_state(&self, _state: &TurtleState) {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 
+    fn write(&self, event: &RunEvent) -> Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)
+            .with_context(|| format!("Could not open run history {}", self.path))?;
+        writeln!(&mut file, "{}", serde_json::to_string(event)?)?;
+        Ok(())
     }
 
-    pub fn add_action(&self, _action: &TurtleAction) {
-        println!("TODO");
-        // use serde::{Serialize, Serializer};
-        //let action_str = serde_json::to_string(&action).unwrap();
-        //println!("SER WORKS: {}", action_str);
+    pub fn add_initial_state(&self, state: &TurtleState) -> Result<()> {
+        self.write(&RunEvent::Initial {
+            location: state.location.clone(),
+            world: state.world.blocks(),
+            names: state.world.block_names(),
+            inventory: state.inventory.clone()
+        })
     }
 
+    pub fn add_action(&self, action: &TurtleAction, result: &TurtleActionReturn, state: &TurtleState, diff: WorldDiff) -> Result<()> {
+        self.write(&RunEvent::Action {
+            action: *action,
+            result: result.clone(),
+            location: state.location.clone(),
+            world: diff
+        })
+    }
 
+    /// Reads back a run written by `RunHistory`.
+    pub fn read(path: &str) -> Result<Vec<RunEvent>> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read run history {}", path))?;
+        contents.lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| serde_json::from_str(line)
+                .with_context(|| format!("Invalid event on line {} of {}", i + 1, path)))
+            .collect()
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turtle_action::{detect, go, inspect, FailureReason};
+    use crate::turtle_state::{Block, StateSerializationPolicy};
+
+    #[test]
+    fn run_is_written_and_read_back() {
+        let dir = "target/test_runs";
+        let history = RunHistory::new("history_test".to_string(), dir).unwrap();
+        let other = RunHistory::new("history_test".to_string(), dir).unwrap();
+        assert_ne!(history.path(), other.path());
+        std::fs::remove_file(other.path()).unwrap();
+        let mut state = TurtleState::new("history_test".to_string(), StateSerializationPolicy::None);
+        state.location.loc_absolute = Some(Coord::new(1, 2, 3));
+        let ore = TurtleActionReturn::InspectSuccess("minecraft:diamond_ore".to_string(), serde_json::Map::new());
+        state.update(&inspect::down(), &ore).unwrap();
+        history.add_initial_state(&state).unwrap();
+
+        let steps = [
+            (go::forward(), TurtleActionReturn::Failure(FailureReason::MovementObstructed)),
+            (detect::up(), TurtleActionReturn::Boolean(false))
+        ];
+        for (action, result) in steps.iter() {
+            let diff = state.update(action, result).unwrap();
+            history.add_action(action, result, &state, diff).unwrap();
+        }
 
-
+        let events = RunHistory::read(history.path()).unwrap();
+        assert_eq!(3, events.len());
+        match &events[0] {
+            RunEvent::Initial {location, world, names, ..} => {
+                assert_eq!(Some(Coord::new(1, 2, 3)), location.loc_absolute);
+                assert_eq!(&vec![(Coord::new(1, 1, 3), Block::Block)], world);
+                assert_eq!(1, names.len());
+                assert_eq!((Coord::new(1, 1, 3), "minecraft:diamond_ore"), (names[0].0.clone(), names[0].1.name.as_str()));
+            },
+            x => panic!("Expected initial state, got {:?}", x)
+        }
+        match &events[1] {
+            RunEvent::Action {action, result, location, world} => {
+                assert_eq!(&go::forward(), action);
+                assert_eq!(&TurtleActionReturn::Failure(FailureReason::MovementObstructed), result);
+                assert_eq!(state.location.loc_absolute, location.loc_absolute);
+                assert_eq!(&vec![(Coord::new(2, 2, 3), Block::Block)], world);
+            },
+            x => panic!("Expected an action, got {:?}", x)
+        }
+        match &events[2] {
+            RunEvent::Action {world, ..} => assert_eq!(&vec![(Coord::new(1, 3, 3), Block::Air)], world),
+            x => panic!("Expected an action, got {:?}", x)
+        }
+        std::fs::remove_file(history.path()).unwrap();
+    }
+}
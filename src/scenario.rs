@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use anyhow::{anyhow, Context, Result};
+use serde_derive::Deserialize;
+use crate::turtle_rotation::AxisDirection;
+use crate::turtle_state::Coord;
+use crate::block_palette::BlockIdentity;
+use crate::world_simulator::{Entity, Runner};
+
+/// A simulated test world with everything the turtle starts with, and what it should achieve.
+/// Read from `tests/state/<name>/scenario.json` by `Runner::from_scenario`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub palette: HashMap<String, PaletteEntry>, // glyph of the layers -> block
+    pub layers: Vec<Layer>,
+    pub turtle: TurtleStart,
+    #[serde(default)]
+    pub inventory: Vec<SlotContents>,
+    pub fuel: Option<Fuel>, // unlimited if not given
+    pub gps: Option<Region>, // gps works everywhere if not given
+    #[serde(default)]
+    pub chests: Vec<Chest>,
+    #[serde(default)]
+    pub entities: Vec<EntityEntry>,
+    #[serde(default)]
+    pub expect: Expectations
+}
+
+/// A block of the palette, either just the name or the name with a block state
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteEntry {
+    Name(String),
+    Block(BlockIdentity)
+}
+
+impl PaletteEntry {
+    fn block(&self) -> BlockIdentity {
+        match self {
+            PaletteEntry::Name(name) => BlockIdentity::new(name),
+            PaletteEntry::Block(block) => block.clone()
+        }
+    }
+}
+
+/// One y level of the world. Like in `state.txt`, the first row has the largest x and the
+/// columns go from the smallest z up.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Layer {
+    pub min: Coord,
+    pub rows: Vec<String>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TurtleStart {
+    pub pos: Coord,
+    #[serde(default = "TurtleStart::default_direction")]
+    pub direction: AxisDirection,
+    #[serde(default = "TurtleStart::known")]
+    pub location_known: bool,
+    #[serde(default)]
+    pub world_known: bool
+}
+
+impl TurtleStart {
+    fn default_direction() -> AxisDirection {
+        AxisDirection::Xp
+    }
+
+    fn known() -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlotContents {
+    pub slot: u8,
+    pub name: String,
+    pub count: u32
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fuel {
+    pub level: u32,
+    pub limit: u32
+}
+
+/// A box of coordinates, both corners included
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Region {
+    pub min: Coord,
+    pub max: Coord
+}
+
+impl Region {
+    pub fn contains(&self, coord: &Coord) -> bool {
+        (self.min.0..=self.max.0).contains(&coord.0)
+            && (self.min.1..=self.max.1).contains(&coord.1)
+            && (self.min.2..=self.max.2).contains(&coord.2)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Chest {
+    pub pos: Coord,
+    #[serde(default)]
+    pub items: Vec<(String, u32)>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EntityEntry {
+    pub pos: Coord,
+    pub name: String,
+    pub health: u32,
+    #[serde(default)]
+    pub drops: Vec<(String, u32)>
+}
+
+impl EntityEntry {
+    pub fn entity(&self) -> Entity {
+        let drops: Vec<(&str, u32)> = self.drops.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+        Entity::new(&self.name, self.health, &drops)
+    }
+}
+
+/// What should be true of the simulated world when the program has finished
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectations {
+    pub position: Option<Coord>,
+    pub direction: Option<AxisDirection>,
+    #[serde(default)]
+    pub items: Vec<(String, u32)>, // at least this many in the inventory
+    #[serde(default)]
+    pub blocks: Vec<ExpectedBlock>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExpectedBlock {
+    pub pos: Coord,
+    pub name: String // minecraft:air for air
+}
+
+impl Scenario {
+    pub const AIR: &'static str = "minecraft:air";
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read scenario {}", path))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid scenario {}", path))
+    }
+
+    /// The glyphs of `state.txt` work without a palette
+    fn default_block(glyph: char) -> Option<&'static str> {
+        match glyph {
+            '.' => Some(Scenario::AIR),
+            '█' => Some(Runner::DEFAULT_BLOCK),
+            '▓' => Some(Runner::BEDROCK),
+            '~' => Some(Runner::WATER),
+            '≈' => Some(Runner::LAVA),
+            _ => None
+        }
+    }
+
+    /// Blocks of the layers, None for air. Spaces are left unknown.
+    pub fn blocks(&self) -> Result<Vec<(Coord, Option<BlockIdentity>)>> {
+        let mut result = vec![];
+        for layer in &self.layers {
+            let max_x = layer.min.0 + layer.rows.len() as i32 - 1;
+            for (i, row) in layer.rows.iter().enumerate() {
+                for (j, glyph) in row.chars().enumerate() {
+                    let coord = Coord::new(max_x - i as i32, layer.min.1, layer.min.2 + j as i32);
+                    if glyph == ' ' {
+                        continue;
+                    }
+                    let block = match (self.palette.get(&glyph.to_string()), Scenario::default_block(glyph)) {
+                        (Some(entry), _) => entry.block(),
+                        (None, Some(name)) => BlockIdentity::new(name),
+                        (None, None) => return Err(anyhow!("Glyph '{}' at {:?} is not in the palette", glyph, coord))
+                    };
+                    result.push((coord, Some(block).filter(|block| block.name != Scenario::AIR)));
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Expectations {
+    /// Checks the expectations against the shadow world of the runner
+    pub fn check(&self, runner: &Runner) -> Result<()> {
+        let mut failures = vec![];
+        let location = runner.shadow_location();
+        if let Some(position) = &self.position {
+            if location.loc_absolute.as_ref() != Some(position) {
+                failures.push(format!("position {:?} instead of {:?}", location.loc_absolute, position));
+            }
+        }
+        if let Some(direction) = &self.direction {
+            if &location.direction_absolute != direction {
+                failures.push(format!("direction {:?} instead of {:?}", location.direction_absolute, direction));
+            }
+        }
+        for (name, count) in &self.items {
+            let actual = runner.shadow_inventory().count_of(name);
+            if actual < *count {
+                failures.push(format!("{} {} instead of at least {}", actual, name, count));
+            }
+        }
+        for expected in &self.blocks {
+            let actual = runner.shadow_block(&expected.pos).map_or(Scenario::AIR.to_string(), |block| block.name);
+            if actual != expected.name {
+                failures.push(format!("{} at {:?} instead of {}", actual, expected.pos, expected.name));
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Scenario expectations not met: {}", failures.join(", ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_use_the_palette() {
+        let scenario: Scenario = serde_json::from_str(r#"{
+            "palette": {"i": "minecraft:iron_ore", "l": {"name": "minecraft:oak_log", "state": {"axis": "y"}}},
+            "layers": [{"min": [0, 5, 0], "rows": ["i.", "l█"]}],
+            "turtle": {"pos": [0, 0, 0]}
+        }"#).unwrap();
+        let blocks = scenario.blocks().unwrap();
+        assert_eq!((Coord::new(1, 5, 0), Some(BlockIdentity::new("minecraft:iron_ore"))), blocks[0]);
+        assert_eq!((Coord::new(1, 5, 1), None), blocks[1]);
+        assert_eq!(Some("y"), blocks[2].1.as_ref().unwrap().state["axis"].as_str());
+        assert_eq!((Coord::new(0, 5, 1), Some(BlockIdentity::new(Runner::DEFAULT_BLOCK))), blocks[3]);
+        assert_eq!(AxisDirection::Xp, scenario.turtle.direction);
+        assert!(scenario.turtle.location_known);
+
+        let unknown: Scenario = serde_json::from_str(r#"{"layers": [{"min": [0, 0, 0], "rows": ["x"]}], "turtle": {"pos": [0, 0, 0]}}"#).unwrap();
+        assert!(unknown.blocks().is_err());
+    }
+}
@@ -1,74 +1,73 @@
+use crate::report;
 use crate::turtle_program::*;
 use crate::turtle_action::*;
 use crate::turtle_state::*;
 use crate::run_history::*;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 pub struct Turtle {
     pub id: String,
     pub program: Box<dyn TurtleProgram>,
     pub last_action: Option<TurtleAction>,
     pub state: TurtleState,
-    pub run_history: RunHistory
+    pub run_history: Option<RunHistory> // None when the run isn't recorded
 }
 
 impl Turtle {
-    pub fn new(name: String, ser_policy: StateSerializationPolicy) -> Self {
-
-        let run_history =  RunHistory::new(name.clone());
+    pub fn new(name: String, ser_policy: StateSerializationPolicy, runs_dir: &str) -> Result<Self> {
         let state = TurtleState::new(name.clone(), ser_policy);
-        run_history.add_initial_state(&state);
-        Turtle {
-            id: name.clone(),
-            program: Box::new(NoProgram{}),
-            last_action: None,
-            state,
-            run_history
-        }
+        let mut turtle = Turtle::from(name, state);
+        turtle.record_to(runs_dir)?;
+        Ok(turtle)
     }
 
+    /// A turtle with the given state, whose run isn't recorded
     pub fn from(name: String, state: TurtleState) -> Self {
-
-        let run_history =  RunHistory::new(name.clone());
-        run_history.add_initial_state(&state);
         Turtle {
-            id: name.clone(),
+            id: name,
             program: Box::new(NoProgram{}),
             last_action: None,
             state,
-            run_history
+            run_history: None
         }
     }
 
+    /// Records the run from the current state on, to a new log in `runs_dir`
+    pub fn record_to(&mut self, runs_dir: &str) -> Result<()> {
+        let run_history = RunHistory::new(self.id.clone(), runs_dir)?;
+        run_history.add_initial_state(&self.state)?;
+        self.run_history = Some(run_history);
+        Ok(())
+    }
+
     fn program_state(&self) -> ProgramState {
-        program_state(&self.program)
+        program_state(self.program.as_ref())
     }
 
     pub fn set_program(&mut self, program: Box<dyn TurtleProgram>) {
         self.program = program;
-        println!("Set program to {}", self.program.name());
-    }
-
-    fn record(&mut self, action: TurtleAction) {
-
-        self.last_action = Some(action);
-        self.run_history.add_action(&action);
+        report!(Normal, "Set program to {}", self.program.name());
     }
 
-    pub fn update(&mut self, result: &TurtleActionReturn) {
-        let action = self.last_action.as_ref().unwrap();
-        self.state.update(action, result);
+    pub fn update(&mut self, result: &TurtleActionReturn) -> Result<()> {
+        let action = self.last_action.as_ref().ok_or_else(|| anyhow!("Received a result before any action was sent"))?;
+        let diff = self.state.update(action, result)?;
         self.program.update(&self.state, action, result);
+        // The turtle is up to date even when the action can't be recorded
+        if let Some(run_history) = &self.run_history {
+            run_history.add_action(action, result, &self.state, diff)?;
+        }
+        Ok(())
     }
 
 
-    pub fn next(&mut self) -> Result<&TurtleAction> {
+    /// The action the turtle should execute next, Stop once the program has finished
+    pub fn next_action(&mut self) -> Result<&TurtleAction> {
         let action = match self.program_state() {
             ProgramState::HasInstructions(_) => self.program.next()?,
             ProgramState::Finished => TurtleAction::Stop,
-            _ => panic!()
+            ProgramState::_Waiting(_) => return Err(anyhow!("Program {} is still waiting for the turtle", self.program.name()))
         };
-        self.record(action);
-        Ok(&self.last_action.as_ref().unwrap())
+        Ok(self.last_action.insert(action))
     }
 }
\ No newline at end of file
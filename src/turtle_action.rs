@@ -2,6 +2,7 @@ use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
 use crate::{turtle_rotation::*};
 use crate::{vec3::Vec3};
+use crate::protocol::{ApiArg, TurtleApiCall};
 /*
 1. Generate as many actions as can be generated
 2. Send actions
@@ -10,48 +11,31 @@ use crate::{vec3::Vec3};
 
 Actions could be enum variants. They can be converted to turtle calls, and they can have structure-like prperties
 */
-
-trait TurtleActionSerializable {
-    fn to_api_call(&self) -> serde_json::Value;
-}
-
-
-#[derive(Serialize, Deserialize)]
-pub struct TurtleApiCall {
-    cmd: String,
-    arg1: serde_json::Value,
-    arg2: serde_json::Value
-}
-
-impl TurtleApiCall {
-    fn new(cmd: &str) -> Self {
-        TurtleApiCall{cmd: cmd.to_string(), arg1: Value::Null, arg2: Value::Null}
-    }
-
-    fn new_wargs(cmd: &str, arg1: Value, arg2: Value) -> Self {
-        TurtleApiCall{cmd: cmd.to_string(), arg1: arg1, arg2: arg2}
-    }
-}
-
-#[derive(Debug, Clone, PartialEq, Hash, Eq, Copy)]
+#[derive(Debug, Clone, PartialEq, Hash, Eq, Copy, Serialize, Deserialize)]
 pub enum TurtleAction {
     Turn {direction: RelativeDirection},
     Move {direction: RelativeDirection},
     Dig {direction: RelativeDirection},
     Detect {direction: RelativeDirection},
     Place {direction: RelativeDirection},
-    Drop {direction: RelativeDirection},
+    Drop {direction: RelativeDirection, count: Option<u32>}, // without count, the whole selected stack
     Attack {direction: RelativeDirection},
-    Suck {direction: RelativeDirection},
+    Suck {direction: RelativeDirection, count: Option<u32>}, // without count, up to a stack
     Inspect {direction: RelativeDirection},
     Compare {direction: RelativeDirection},
     Select {slot: u8}, // [1, 16]
     ItemCount {slot: u8},
     ItemSpace {slot: u8},
-    ItemDetail {slot: u8}, // this also has a second parameter, "detailed", which supposedly is slower.
-    TransferTo {slot: u8},
+    ItemDetail {slot: u8, detailed: bool}, // detailed also returns display name, tags etc., but is slower
+    TransferTo {slot: u8, count: Option<u32>}, // without count, as much of the selected stack as fits
     CompareTo {slot: u8},
     GpsLocate {timeout_ms: u32, debug: bool},
+    Refuel {count: Option<u32>}, // without count, the whole selected stack is consumed
+    GetFuelLevel,
+    GetFuelLimit,
+    Equip {direction: RelativeDirection}, // Left or Right
+    Craft {limit: Option<u32>}, // without limit, as many as possible are crafted
+    GetSelectedSlot,
     Stop
 }
 
@@ -127,16 +111,22 @@ pub mod attack {
 
 pub mod suck {
     use super::*;
-    pub const fn forward() -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Forward}}
-    pub const fn up() -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Up}}
-    pub const fn down() -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Down}}
+    pub const fn forward() -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Forward, count: None}}
+    pub const fn up() -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Up, count: None}}
+    pub const fn down() -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Down, count: None}}
+    pub const fn forward_count(count: u32) -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Forward, count: Some(count)}}
+    pub const fn up_count(count: u32) -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Up, count: Some(count)}}
+    pub const fn down_count(count: u32) -> TurtleAction {TurtleAction::Suck{direction:RelativeDirection::Down, count: Some(count)}}
 }
 
 pub mod drop {
     use super::*;
-    pub const fn forward() -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Forward}}
-    pub const fn up() -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Up}}
-    pub const fn down() -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Down}}
+    pub const fn forward() -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Forward, count: None}}
+    pub const fn up() -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Up, count: None}}
+    pub const fn down() -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Down, count: None}}
+    pub const fn forward_count(count: u32) -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Forward, count: Some(count)}}
+    pub const fn up_count(count: u32) -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Up, count: Some(count)}}
+    pub const fn down_count(count: u32) -> TurtleAction {TurtleAction::Drop{direction:RelativeDirection::Down, count: Some(count)}}
 }
 pub mod inventory {
     use super::*;
@@ -153,11 +143,31 @@ pub mod inventory {
     }
 
     pub const fn detail(slot: u8) -> TurtleAction {
-        TurtleAction::ItemDetail{slot }
+        TurtleAction::ItemDetail{slot, detailed: false}
+    }
+
+    pub const fn detail_full(slot: u8) -> TurtleAction {
+        TurtleAction::ItemDetail{slot, detailed: true}
+    }
+
+    pub const fn selected() -> TurtleAction {
+        TurtleAction::GetSelectedSlot
+    }
+
+    pub const fn craft() -> TurtleAction {
+        TurtleAction::Craft{limit: None}
+    }
+
+    pub const fn craft_limit(limit: u32) -> TurtleAction {
+        TurtleAction::Craft{limit: Some(limit)}
     }
 
     pub const fn transfer_to(slot: u8) -> TurtleAction {
-        TurtleAction::TransferTo{slot }
+        TurtleAction::TransferTo{slot, count: None}
+    }
+
+    pub const fn transfer_to_count(slot: u8, count: u32) -> TurtleAction {
+        TurtleAction::TransferTo{slot, count: Some(count)}
     }
 
     pub const fn compare_to(slot: u8) -> TurtleAction {
@@ -165,6 +175,12 @@ pub mod inventory {
     }
 }
 
+pub mod equip {
+    use super::*;
+    pub const fn left() -> TurtleAction {TurtleAction::Equip{direction:RelativeDirection::Left}}
+    pub const fn right() -> TurtleAction {TurtleAction::Equip{direction:RelativeDirection::Right}}
+}
+
 pub mod gps {
     use super::*;
     pub const fn locate() -> TurtleAction {
@@ -172,27 +188,52 @@ pub mod gps {
     }
 }
 
+pub mod fuel {
+    use super::*;
+    pub const fn refuel() -> TurtleAction {
+        TurtleAction::Refuel{count: None}
+    }
+
+    pub const fn refuel_count(count: u32) -> TurtleAction {
+        TurtleAction::Refuel{count: Some(count)}
+    }
+
+    pub const fn level() -> TurtleAction {
+        TurtleAction::GetFuelLevel
+    }
+
+    pub const fn limit() -> TurtleAction {
+        TurtleAction::GetFuelLimit
+    }
+}
+
 
 
 impl TurtleAction {
+    fn three_direction_count_call(name: &str, direction: &RelativeDirection, count: &Option<u32>) -> TurtleApiCall {
+        let mut call = TurtleAction::three_direction_call(name, direction);
+        call.arg1 = count.map(|x| ApiArg::Int(x as i64));
+        call
+    }
+
     fn three_direction_call(name: &str, direction: &RelativeDirection) -> TurtleApiCall {
         let call = match direction {
             RelativeDirection::Forward => format!("turtle.{}", name),
             RelativeDirection::Up => format!("turtle.{}Up", name),
             RelativeDirection::Down => format!("turtle.{}Down", name),
-            _ => panic!(format!("Unsupported {} direction {:?}", name, direction))
+            _ => panic!("Unsupported {} direction {:?}", name, direction)
             };
         TurtleApiCall::new(call.as_str())
     }
     fn slot_call(name: &str, slot: &u8) -> TurtleApiCall {
         match slot {
-            1..=16 => TurtleApiCall::new_wargs(format!("turtle.{}", name).as_str(), Value::from(*slot), Value::Null),
-            _ => panic!(format!("Slot index out of range: {}, should be [1, 16]", slot))
+            1..=16 => TurtleApiCall::new_wargs(format!("turtle.{}", name).as_str(), Some(ApiArg::Int(*slot as i64)), None),
+            _ => panic!("Slot index out of range: {}, should be [1, 16]", slot)
         }
     }
 
     fn gps_call(timeout_ms: &u32, debug: &bool) -> TurtleApiCall {
-        TurtleApiCall::new_wargs("gps.locate", Value::from((*timeout_ms as f64) / 1000f64), Value::from(*debug))
+        TurtleApiCall::new_wargs("gps.locate", Some(ApiArg::Number((*timeout_ms as f64) / 1000f64)), Some(ApiArg::Bool(*debug)))
     }
     
     pub fn to_api_call(&self) -> TurtleApiCall {
@@ -201,7 +242,7 @@ impl TurtleAction {
             let call = match direction {
                     RelativeDirection::Right => "turtle.turnRight",
                     RelativeDirection::Left => "turtle.turnLeft",
-                    _ => panic!(format!("Unsupported turn direction {:?}", direction))
+                    _ => panic!("Unsupported turn direction {:?}", direction)
                 };
                 TurtleApiCall::new(call)
             },
@@ -211,7 +252,7 @@ impl TurtleAction {
                     RelativeDirection::Backward => "turtle.back",
                     RelativeDirection::Up => "turtle.up",
                     RelativeDirection::Down => "turtle.down",
-                    _ => panic!(format!("Unsupported move direction {:?}", direction))
+                    _ => panic!("Unsupported move direction {:?}", direction)
                 };
                 TurtleApiCall::new(call)
             },
@@ -221,12 +262,12 @@ impl TurtleAction {
                 TurtleAction::three_direction_call("detect", direction),
             TurtleAction::Place {direction} => 
                 TurtleAction::three_direction_call("place", direction),
-            TurtleAction::Drop {direction} => 
-                TurtleAction::three_direction_call("drop", direction),
+            TurtleAction::Drop {direction, count} =>
+                TurtleAction::three_direction_count_call("drop", direction, count),
             TurtleAction::Attack {direction} => 
                 TurtleAction::three_direction_call("attack", direction),
-            TurtleAction::Suck {direction} => 
-                TurtleAction::three_direction_call("suck", direction),
+            TurtleAction::Suck {direction, count} =>
+                TurtleAction::three_direction_count_call("suck", direction, count),
             TurtleAction::Inspect {direction } =>
                 TurtleAction::three_direction_call("inspect", direction),
             TurtleAction::Compare {direction } =>
@@ -234,34 +275,71 @@ impl TurtleAction {
             TurtleAction::Select {slot } => TurtleAction::slot_call("select", slot),
             TurtleAction::ItemCount {slot } => TurtleAction::slot_call("getItemCount", slot),
             TurtleAction::ItemSpace {slot } => TurtleAction::slot_call("getItemSpace", slot),
-            TurtleAction::ItemDetail {slot } => TurtleAction::slot_call("getItemDetail", slot),
-            TurtleAction::TransferTo {slot } => TurtleAction::slot_call("transferTo", slot),
+            TurtleAction::ItemDetail {slot, detailed} => {
+                let mut call = TurtleAction::slot_call("getItemDetail", slot);
+                call.arg2 = Some(ApiArg::Bool(*detailed));
+                call
+            },
+            TurtleAction::TransferTo {slot, count} => {
+                let mut call = TurtleAction::slot_call("transferTo", slot);
+                call.arg2 = count.map(|x| ApiArg::Int(x as i64));
+                call
+            },
             TurtleAction::CompareTo {slot } => TurtleAction::slot_call("compareTo", slot),
             TurtleAction::Stop => TurtleApiCall::new("stop"),
-            TurtleAction::GpsLocate {timeout_ms, debug} => TurtleAction::gps_call(timeout_ms, debug)
+            TurtleAction::GpsLocate {timeout_ms, debug} => TurtleAction::gps_call(timeout_ms, debug),
+            TurtleAction::Refuel {count} =>
+                TurtleApiCall::new_wargs("turtle.refuel", count.map(|x| ApiArg::Int(x as i64)), None),
+            TurtleAction::GetFuelLevel => TurtleApiCall::new("turtle.getFuelLevel"),
+            TurtleAction::GetFuelLimit => TurtleApiCall::new("turtle.getFuelLimit"),
+            TurtleAction::Equip {direction} => {
+                let call = match direction {
+                    RelativeDirection::Left => "turtle.equipLeft",
+                    RelativeDirection::Right => "turtle.equipRight",
+                    _ => panic!("Unsupported equip direction {:?}", direction)
+                };
+                TurtleApiCall::new(call)
+            },
+            TurtleAction::Craft {limit} =>
+                TurtleApiCall::new_wargs("turtle.craft", limit.map(|x| ApiArg::Int(x as i64)), None),
+            TurtleAction::GetSelectedSlot => TurtleApiCall::new("turtle.getSelectedSlot")
         }
     }
 }
 
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum FailureReason {
     MovementObstructed, // move
+    OutOfFuel, // move
+    TooHighToMove, // move
+    TooLowToMove, // move
+    CanNotLeaveWorld, // move
     NoBlockToInspect, // inspect
     NoItemsToPlace, // place
     CanNotPlaceItemHere, // place
     CanNotPlaceBlockHere, // place
+    CanNotPlaceInProtectedArea, // place
     NothingToDigHere,   // dig
+    NoToolToDig, // dig
     NothingToAttackHere, // attack
+    NoToolToAttack, // attack
     NoItemsToTake, // suck
     NoItemsToDrop, // drop
     SlotIsEmpty, // itemDetail
     NoSpaceForItems, // transferTo
     UnbreakableBlockDetected, // dig
-    GpsLocateFailure
+    CanNotBreakProtectedBlock, // dig
+    NotAValidUpgrade, // equip
+    NoMatchingRecipes, // craft
+    NoItemsToCombust, // refuel
+    ItemsNotCombustible, // refuel
+    GpsLocateFailure,
+    Unspecified, // the call failed without giving a reason
+    Other(String) // reasons we don't know about, e.g. from mods or newer CC:Tweaked versions
 }
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum TurtleActionReturn {
     Success,
     Failure(FailureReason),
@@ -269,23 +347,76 @@ pub enum TurtleActionReturn {
     DetailSuccess(serde_json::Map<String, Value>),
     Boolean(bool),
     Number(u32),
-    Coordinate(Vec3<i32>)
+    Coordinate(Vec3<i32>),
+    Unlimited // fuel level and limit when fuel is disabled in the server config
 }
 
 
 pub fn parse_failure_reason(reason: &str) -> FailureReason {
     match reason {
         "Movement obstructed" => FailureReason::MovementObstructed,
+        "Out of fuel" => FailureReason::OutOfFuel,
+        "Too high to move" => FailureReason::TooHighToMove,
+        "Too low to move" => FailureReason::TooLowToMove,
+        "Cannot leave the world" |
+        "Cannot leave loaded world" |
+        "Cannot pass the world border" => FailureReason::CanNotLeaveWorld,
         "No block to inspect" => FailureReason::NoBlockToInspect,
         "No items to place" => FailureReason::NoItemsToPlace,
         "Cannot place item here" => FailureReason::CanNotPlaceItemHere,
         "Cannot place block here" => FailureReason::CanNotPlaceBlockHere,
+        "Cannot place in protected area" => FailureReason::CanNotPlaceInProtectedArea,
         "Nothing to dig here" => FailureReason::NothingToDigHere,
+        "No tool to dig with" => FailureReason::NoToolToDig,
         "Nothing to attack here" => FailureReason::NothingToAttackHere,
+        "No tool to attack with" => FailureReason::NoToolToAttack,
         "No items to take" => FailureReason::NoItemsToTake,
         "No items to drop" => FailureReason::NoItemsToDrop,
         "No space for items" => FailureReason::NoSpaceForItems,
-        "Unbreakable block detected" => FailureReason::UnbreakableBlockDetected,
-        _ => panic!(format!("Unknown reason {}", reason))
+        "Unbreakable block detected" |
+        "Cannot break unbreakable block" => FailureReason::UnbreakableBlockDetected,
+        "Cannot break protected block" => FailureReason::CanNotBreakProtectedBlock,
+        "Not a valid upgrade" => FailureReason::NotAValidUpgrade,
+        "No matching recipes" => FailureReason::NoMatchingRecipes,
+        "No items to combust" => FailureReason::NoItemsToCombust,
+        "Items not combustible" => FailureReason::ItemsNotCombustible,
+        x => FailureReason::Other(x.to_string())
     }
-}
\ No newline at end of file
+}
+
+impl FailureReason {
+    /// The message the turtle API fails with, None for a failure without a reason
+    pub fn message(&self) -> Option<&str> {
+        let message = match self {
+            FailureReason::MovementObstructed => "Movement obstructed",
+            FailureReason::OutOfFuel => "Out of fuel",
+            FailureReason::TooHighToMove => "Too high to move",
+            FailureReason::TooLowToMove => "Too low to move",
+            FailureReason::CanNotLeaveWorld => "Cannot leave the world",
+            FailureReason::NoBlockToInspect => "No block to inspect",
+            FailureReason::NoItemsToPlace => "No items to place",
+            FailureReason::CanNotPlaceItemHere => "Cannot place item here",
+            FailureReason::CanNotPlaceBlockHere => "Cannot place block here",
+            FailureReason::CanNotPlaceInProtectedArea => "Cannot place in protected area",
+            FailureReason::NothingToDigHere => "Nothing to dig here",
+            FailureReason::NoToolToDig => "No tool to dig with",
+            FailureReason::NothingToAttackHere => "Nothing to attack here",
+            FailureReason::NoToolToAttack => "No tool to attack with",
+            FailureReason::NoItemsToTake => "No items to take",
+            FailureReason::NoItemsToDrop => "No items to drop",
+            FailureReason::SlotIsEmpty => "Slot is empty",
+            FailureReason::NoSpaceForItems => "No space for items",
+            FailureReason::UnbreakableBlockDetected => "Cannot break unbreakable block",
+            FailureReason::CanNotBreakProtectedBlock => "Cannot break protected block",
+            FailureReason::NotAValidUpgrade => "Not a valid upgrade",
+            FailureReason::NoMatchingRecipes => "No matching recipes",
+            FailureReason::NoItemsToCombust => "No items to combust",
+            FailureReason::ItemsNotCombustible => "Items not combustible",
+            FailureReason::GpsLocateFailure => "Could not determine position",
+            FailureReason::Unspecified => return None,
+            FailureReason::Other(message) => message
+        };
+        Some(message)
+    }
+}
+
@@ -1,7 +1,7 @@
+use crate::report;
 use crate::{turtle_action::*, turtle_rotation::AxisDirection, turtle_state::*, vec3::Vec3};
 use anyhow::{anyhow, Result};
 use pathfind::RTAStar;
-use serde_json;
 use serde_derive::{Deserialize, Serialize};
 extern crate rand;
 use rand::{seq::SliceRandom};
@@ -24,7 +24,7 @@ pub enum ProgramState {
     HasInstructions(f64) // has instructions that can be delivered to turtle
 }
 
-pub(crate) fn program_state(program: &Box<dyn TurtleProgram>) -> ProgramState {
+pub(crate) fn program_state(program: &dyn TurtleProgram) -> ProgramState {
     let progress = program.progress();
     if progress.0 == progress.1 {
         return ProgramState::Finished;
@@ -33,10 +33,9 @@ pub(crate) fn program_state(program: &Box<dyn TurtleProgram>) -> ProgramState {
     ProgramState::HasInstructions(progress_f)
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StartProgramMsg {
-    msgtype: String, // Should be "start"
-    args: Vec<String>
+    pub args: Vec<String>
 }
 
 // #[derive(Debug)]
@@ -61,7 +60,7 @@ impl RotateProgram {
             true => RelativeDirection::Right,
             false => RelativeDirection::Left
         };
-        Ok(RotateProgram {steps: steps_i32.abs() as u32, steps_remaining:steps_i32 as u32, direction: direction})
+        Ok(RotateProgram {steps: steps_i32.unsigned_abs(), steps_remaining: steps_i32.unsigned_abs(), direction})
     }
 }
 
@@ -71,7 +70,7 @@ impl TurtleProgram for RotateProgram {
     //     Ok(())
     // }
     fn progress(&self) -> (u32, u32) {
-        return (self.steps-self.steps_remaining, self.steps)
+        (self.steps-self.steps_remaining, self.steps)
     }
 
     fn name(&self)  -> &str {"rotate"}
@@ -79,8 +78,7 @@ impl TurtleProgram for RotateProgram {
     fn next(&mut self) -> Result<TurtleAction> {
         // self.actions_remaining.push(TurtleAction::Turn{direction: RelativeDirection::Left});
         assert!(self.steps_remaining > 0);
-        Ok(go::forward())
-        // Ok(TurtleAction::Turn{direction: RelativeDirection::Left})
+        Ok(TurtleAction::Turn{direction: self.direction})
     }
 
     fn update(&mut self, _state: &TurtleState, _action: &TurtleAction, _result: &TurtleActionReturn)  {
@@ -135,13 +133,6 @@ pub fn create_program(msg: &StartProgramMsg ) -> Result<Box<dyn TurtleProgram>>
 }
 
 
-// Message that the lua client sends in response to a command it just tried to execute
-#[derive(Serialize, Deserialize)]
-pub struct TurtleResponseMsg {
-    pub msgtype: String,
-    pub result: serde_json::Value // the return value
-}
-
 // fn packtable_to_vec(table: serde_json::Value) -> Result<Vec<serde_json::Value>> {
 //     let n = table["n"].to_string().parse::<usize>()?;
 //     let mut result = vec![];
@@ -167,7 +158,7 @@ impl PathfindingTestProgram {
 
 impl TurtleProgram for PathfindingTestProgram {
     fn next(&mut self) -> Result<TurtleAction> {
-        println!("PATHFINDING");
+        report!(Debug, "PATHFINDING");
         self.pathfinder.next()
 
     }
@@ -181,8 +172,8 @@ impl TurtleProgram for PathfindingTestProgram {
     }
 
     fn update(&mut self, state: &TurtleState,  _action: &TurtleAction, _result: &TurtleActionReturn) {
-        println!("UPDATE PATHFINDING");
-        self.pathfinder.update(&state);
+        report!(Debug, "UPDATE PATHFINDING");
+        self.pathfinder.update(state);
     }
 }
 
@@ -190,16 +181,21 @@ impl TurtleProgram for PathfindingTestProgram {
 #[derive(Debug)]
 pub struct LocationTestProgram {
     // state: LocationState,
-    gps_initialized: bool,
     random: RandomProgram,
     cur_step: u32
 }
 
+impl Default for LocationTestProgram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LocationTestProgram {
     pub fn new() -> Self {
         let random = RandomProgram::new(false,true, true);
         // let state = LocationState::new();
-        LocationTestProgram{random:random, gps_initialized:false, cur_step:0}
+        LocationTestProgram{random, cur_step:0}
     }
 }
 
@@ -221,14 +217,14 @@ impl TurtleProgram for LocationTestProgram {
     }
 
     fn update(&mut self, state: &TurtleState,  action: &TurtleAction, result: &TurtleActionReturn) {
-        println!("{:?}", state.location.loc_absolute);
+        report!(Debug, "{:?}", state.location.loc_absolute);
         match action {
             TurtleAction::Move{..}|
             TurtleAction::Turn{..} => {
                 // self.state.update(action, result);
             },
             TurtleAction::GpsLocate{..} => {
-                if let TurtleActionReturn::Coordinate(_) = &*result {
+                if let TurtleActionReturn::Coordinate(_) = result {
                     // self.state.update(action, result);
                 } else {
                     if state.location.loc_absolute.is_none() {
@@ -236,7 +232,7 @@ impl TurtleProgram for LocationTestProgram {
                         panic!("Could not determine gps");
                     } else {
                         // Execution _ended up_ out of gps range
-                        println!("Out of GPS range");
+                        report!(Debug, "Out of GPS range");
                     }
                 }
             },
@@ -279,7 +275,7 @@ impl RandomProgram {
             inventory::transfer_to(1), inventory::transfer_to(2),inventory::transfer_to(3), inventory::transfer_to(16),
             gps::locate()
             ];
-        RandomProgram{actions:actions, drop:enable_drop, only_move: only_move, horizontal: horizontal}
+        RandomProgram{actions, drop:enable_drop, only_move, horizontal}
     }
 }
 
@@ -295,17 +291,17 @@ impl TurtleProgram for RandomProgram {
                 TurtleAction::Move{direction} if self.horizontal && matches!(direction, RelativeDirection::Up|RelativeDirection::Down) => self.next(),
                 TurtleAction::Move{..}|
                 TurtleAction::Turn{..}|
-                TurtleAction::GpsLocate{..} => Ok(action.clone()),
+                TurtleAction::GpsLocate{..} => Ok(*action),
                 _ => self.next()
             }
         } else {
             if !self.drop {
                 match action {
                     TurtleAction::Drop{..} => self.next(),
-                    _ => Ok(action.clone())
+                    _ => Ok(*action)
                 }
             } else {
-                Ok(action.clone())
+                Ok(*action)
             }
         }
     }
@@ -350,7 +346,7 @@ impl TurtleProgram for FromActionsProgram {
 
     fn next(&mut self) -> Result<TurtleAction> {
         if self.index < self.actions.len() {
-            Ok(self.actions[self.index].clone())
+            Ok(self.actions[self.index])
         } else {
             Err(anyhow!("next() called when the program is finished!"))
         }
@@ -391,7 +387,7 @@ impl MultiProgram {
 impl TurtleProgram for MultiProgram {
     fn next(&mut self) -> Result<TurtleAction> {
         let mut from_current = self.current.next();
-        while let Err(_) = from_current {
+        while from_current.is_err() {
 
             let next_program = self.programs.pop_front();
             if next_program.is_none() {
@@ -432,16 +428,24 @@ impl TurtleProgram for MultiProgram {
 pub struct InitGpsProgram {
     strategy: usize,
     step_in_strategy: i32,
-    has_gps: bool
+    gps_failures: u32
+}
+
+impl Default for InitGpsProgram {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InitGpsProgram {
+    /// GPS can time out when the hosts are busy or far away, so a failed locate is tried again
+    const GPS_ATTEMPTS: u32 = 5;
 
     pub fn new() -> Self {
         InitGpsProgram {
             strategy: 0,
             step_in_strategy: -1,
-            has_gps: false
+            gps_failures: 0
         }
     }
 
@@ -453,19 +457,36 @@ impl InitGpsProgram {
             _ => panic!()
         }
     }
+
+    /// Whether the first move of the current strategy is about to enter a known fluid
+    fn next_move_is_hazard(&self, state: &TurtleState) -> bool {
+        let strategy = self.get_cur_strategy();
+        let first_move = strategy.iter().position(|action| matches!(action, TurtleAction::Move{..}));
+        if self.step_in_strategy < 0 || first_move != Some(self.step_in_strategy as usize) {
+            return false;
+        }
+        match &strategy[self.step_in_strategy as usize] {
+            TurtleAction::Move{direction} => state.location.get_dest_position_absolute(direction)
+                .is_some_and(|dest| state.world.is_hazard(&dest)),
+            _ => false
+        }
+    }
 }
 
 impl TurtleProgram for InitGpsProgram {
 
 
     fn next(&mut self) -> Result<TurtleAction> {
+        if self.gps_failures >= InitGpsProgram::GPS_ATTEMPTS {
+            return Err(anyhow!("No gps after {} attempts", self.gps_failures));
+        }
         let strategy = self.get_cur_strategy();
         if self.step_in_strategy == -1 {
             Ok(gps::locate())
         } else if self.step_in_strategy as usize == strategy.len() {
             Err(anyhow!(""))
         }else {
-            Ok(strategy[self.step_in_strategy as usize].clone())
+            Ok(strategy[self.step_in_strategy as usize])
         }
     }
 
@@ -481,7 +502,7 @@ impl TurtleProgram for InitGpsProgram {
         "initgps"
     }
 
-    fn update(&mut self, _state: &TurtleState, action: &TurtleAction, result: &TurtleActionReturn) {
+    fn update(&mut self, state: &TurtleState, action: &TurtleAction, result: &TurtleActionReturn) {
         // println!("{:?} - {:?}", action, result);
         match (action, result) {
             (TurtleAction::Move{..}, TurtleActionReturn::Failure(..)) => {
@@ -489,12 +510,21 @@ impl TurtleProgram for InitGpsProgram {
                 self.step_in_strategy = -1
             },
             (TurtleAction::GpsLocate {..}, TurtleActionReturn::Failure(..)) => {
-                panic!("No gps!")
+                self.gps_failures += 1;
+            },
+            (TurtleAction::GpsLocate {..}, _) => {
+                self.gps_failures = 0;
+                self.step_in_strategy += 1;
             },
             (_, _) => {
                 self.step_in_strategy += 1;
             }
         }
+        // Try another way instead of stepping into lava or water
+        if self.next_move_is_hazard(state) {
+            self.strategy += 1;
+            self.step_in_strategy = -1
+        }
     }
 }
 
@@ -506,7 +536,7 @@ mod tests {
     use super::*;
     #[test]
     fn test_rotate_program_start() {
-        let start_args = vec!["rotate".to_string(), "-4".to_string()];
+        let start_args = ["rotate".to_string(), "-4".to_string()];
         // let start_msg = StartProgramMsg{msgtype:"start".to_string(), args:start_args};
         // let program = create_program(&start_msg);
         // assert!(program.is_ok());
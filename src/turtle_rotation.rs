@@ -1,8 +1,9 @@
+use serde_derive::{Deserialize, Serialize};
 use crate::{vec3::Vec3};
 type Coord = Vec3::<i32>;
 
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash, Copy)]
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Copy, Serialize, Deserialize)]
 pub enum RelativeDirection {
     Forward,
     Backward,
@@ -12,7 +13,7 @@ pub enum RelativeDirection {
     Up
 }
 
-#[derive(PartialEq, Debug, Eq, Hash, Clone)]
+#[derive(PartialEq, Debug, Eq, Hash, Clone, Serialize, Deserialize)]
 pub enum AxisDirection {
     None,
     Xp,
@@ -29,7 +30,7 @@ Zm      Zp
     Xm
 
 */
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Rotation {
     Y0,
     Y90,
@@ -70,7 +71,7 @@ impl Rotation {
                 return rot.clone();
             }
         }
-        panic!(format!("Could not rotate {:?} to {:?}", src, dst))
+        panic!("Could not rotate {:?} to {:?}", src, dst)
     }
 
 
@@ -88,13 +89,13 @@ impl AxisDirection {
     pub const ALL: [AxisDirection;6] = [AxisDirection::Xp, AxisDirection::Zp, AxisDirection::Xm, AxisDirection::Zm, AxisDirection::Yp, AxisDirection::Ym];
 
     pub fn from(unit_vec: &Coord) -> Self {
-        match unit_vec {
-            &AxisDirection::AD_XP => AxisDirection::Xp,
-            &AxisDirection::AD_XM => AxisDirection::Xm,
-            &AxisDirection::AD_ZP => AxisDirection::Zp,
-            &AxisDirection::AD_ZM => AxisDirection::Zm,
-            &AxisDirection::AD_YP => AxisDirection::Yp,
-            &AxisDirection::AD_YM => AxisDirection::Ym,
+        match *unit_vec {
+            AxisDirection::AD_XP => AxisDirection::Xp,
+            AxisDirection::AD_XM => AxisDirection::Xm,
+            AxisDirection::AD_ZP => AxisDirection::Zp,
+            AxisDirection::AD_ZM => AxisDirection::Zm,
+            AxisDirection::AD_YP => AxisDirection::Yp,
+            AxisDirection::AD_YM => AxisDirection::Ym,
             _ => AxisDirection::None
         }
     }
@@ -155,13 +156,13 @@ pub fn get_dest_axisdirection(cur_axis: &AxisDirection, move_direction: &Relativ
         RelativeDirection::Up => AxisDirection::AD_YP,
         RelativeDirection::Down => AxisDirection::AD_YM,
         RelativeDirection::Forward => cur_axis.to_unit_vector(),
-        RelativeDirection::Backward => (-cur_axis.to_unit_vector()),
+        RelativeDirection::Backward => -cur_axis.to_unit_vector(),
         _ => panic!()
     }
 }
 
 pub fn get_dest_pos(cur_pos: &Coord, cur_axis: &AxisDirection, move_direction: &RelativeDirection) -> Coord {
-    let loc_dir = get_dest_axisdirection(&cur_axis, move_direction);
+    let loc_dir = get_dest_axisdirection(cur_axis, move_direction);
     cur_pos + &loc_dir
 }
 
@@ -2,34 +2,79 @@ use core::cmp::{max, min};
 use std::collections::HashMap;
 use std::io::prelude::*;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use serde_derive::{Deserialize, Serialize};
 
+use crate::report;
+use crate::block_palette::{BlockEntry, BlockIdentity, BlockKey, BlockPalette};
 use crate::{turtle_action::*};
 use crate::{turtle_rotation::*};
 use crate::location_state::LocationState;
+use crate::fuel_state::FuelState;
+use crate::inventory_state::InventoryState;
 use crate::vec3::*;
 
 // Guesses the state of turtle by the recorded executed commands.
 pub type Coord = Vec3::<i32>;
+/// Blocks that changed as a result of a single action
+pub type WorldDiff = Vec<(Coord, Block)>;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "kebab-case")] // the same names as on the command line
 pub enum StateSerializationPolicy {
     /// Load state upon initialization, and save after each modification.
     /// String basedirectory,
+    #[serde(alias = "load_and_save")]
     LoadAndSave{load_dir: String, save_dir: String},
     /// Load state upon initialization, never save
+    #[serde(alias = "load_only")]
     LoadOnly{load_dir: String},
     /// Start from clean slate, and save after each modification
+    #[serde(alias = "save_only")]
     SaveOnly{save_dir: String},
     /// Don't load anything, forget everything.
     None
 }
 
+impl StateSerializationPolicy {
+    /// Creates a policy by name, using the same directory for loading and saving.
+    pub fn parse(name: &str, dir: &str) -> Result<Self> {
+        match name {
+            "load-and-save" => Ok(StateSerializationPolicy::LoadAndSave {load_dir: dir.to_string(), save_dir: dir.to_string()}),
+            "load-only" => Ok(StateSerializationPolicy::LoadOnly {load_dir: dir.to_string()}),
+            "save-only" => Ok(StateSerializationPolicy::SaveOnly {save_dir: dir.to_string()}),
+            "none" => Ok(StateSerializationPolicy::None),
+            x => Err(anyhow!("Invalid state policy: {}", x))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateSerializationPolicy::LoadAndSave {..} => "load-and-save",
+            StateSerializationPolicy::LoadOnly {..} => "load-only",
+            StateSerializationPolicy::SaveOnly {..} => "save-only",
+            StateSerializationPolicy::None => "none"
+        }
+    }
+
+    /// Directory the state is loaded from, or saved to if nothing is loaded.
+    pub fn dir(&self) -> Option<&str> {
+        match self {
+            StateSerializationPolicy::LoadAndSave {load_dir, ..}|
+            StateSerializationPolicy::LoadOnly {load_dir} => Some(load_dir),
+            StateSerializationPolicy::SaveOnly {save_dir} => Some(save_dir),
+            StateSerializationPolicy::None => None
+        }
+    }
+}
+
 
 
 pub struct TurtleState {
     pub location: LocationState,
     pub world: WorldState,
+    pub fuel: FuelState,
+    pub inventory: InventoryState,
     pub history: ActionHistory
     // ,pub run: RunHistory
 }
@@ -44,6 +89,8 @@ impl TurtleState {
         TurtleState{
             location,
             world,
+            fuel: FuelState::new(),
+            inventory: InventoryState::new(),
             history: ActionHistory::new()
         }
     }
@@ -52,23 +99,31 @@ impl TurtleState {
         TurtleState {
             location,
             world,
+            fuel: FuelState::new(),
+            inventory: InventoryState::new(),
             history: ActionHistory::new()
         }
     }
 
-    pub fn update(&mut self, action: &TurtleAction, result: &TurtleActionReturn) {
-        self.location.update(action, result);
-        self.world.update(action, result, &self.location);
+    pub fn update(&mut self, action: &TurtleAction, result: &TurtleActionReturn) -> Result<WorldDiff> {
+        self.location.update(action, result)?;
+        let diff = self.world.update(action, result, &self.location);
+        self.fuel.update(action, result);
+        self.inventory.update(action, result);
         self.history.update(action, result);
+        Ok(diff)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Block {
     Unknown,
     Air,
     AirOrGravityBlock,
-    Block
+    Block,
+    Bedrock, // solid and can't be dug
+    Water, // can be moved through and isn't detected, but can be inspected
+    Lava
 }
 
 impl Block {
@@ -77,7 +132,10 @@ impl Block {
             Block::Unknown => ' ',
             Block::Air => '.',
             Block::Block => '█',
-            Block::AirOrGravityBlock => '^'
+            Block::AirOrGravityBlock => '^',
+            Block::Bedrock => '▓',
+            Block::Water => '~',
+            Block::Lava => '≈'
         }
     }
 
@@ -87,31 +145,57 @@ impl Block {
             '.' => Block::Air,
             '█' => Block::Block,
             '^' => Block::AirOrGravityBlock,
+            '▓' => Block::Bedrock,
+            '~' => Block::Water,
+            '≈' => Block::Lava,
             _ => panic!()
         }
     }
+
+    /// The fluid kind of an inspected block name, both source and flowing blocks share the name
+    pub fn fluid(name: &str) -> Option<Self> {
+        match name {
+            "minecraft:water"|"minecraft:flowing_water" => Some(Block::Water),
+            "minecraft:lava"|"minecraft:flowing_lava" => Some(Block::Lava),
+            _ => None
+        }
+    }
+
+    pub fn is_fluid(&self) -> bool {
+        matches!(self, Block::Water|Block::Lava)
+    }
 }
 
 
 pub struct WorldState {
     pub state: HashMap<Coord, Block>,
+    names: HashMap<Coord, BlockKey>, // what inspect has told about the blocks
+    palette: BlockPalette,
     id: String,
     ser_policy: StateSerializationPolicy
 }
 
 impl WorldState {
     pub fn is_obstructed(&self, coord: &Vec3<i32>) -> Option<bool> {
-        if let Some(block) = self.state.get(&coord) {
+        if let Some(block) = self.state.get(coord) {
             match block {
                 Block::Unknown|
                 Block::AirOrGravityBlock => Option::None,
-                Block::Air => Some(false),
-                Block::Block => Some(true)
+                Block::Air|
+                Block::Water|
+                Block::Lava => Some(false),
+                Block::Block|
+                Block::Bedrock => Some(true)
             }
         } else {
             Option::None
         }
     }
+
+    /// Fluids can be moved through, but the turtle loses items to lava and water carries them away
+    pub fn is_hazard(&self, coord: &Coord) -> bool {
+        self.get(coord).is_fluid()
+    }
 }
 
 pub fn dimensions<'a>(iter: impl Iterator<Item= &'a Coord>) -> (Coord, Coord) {
@@ -133,11 +217,18 @@ impl WorldState {
     pub fn new(id: String, ser_policy: StateSerializationPolicy) -> Self {
         let state = WorldState::deserialize_or_empty(&id, &ser_policy);
         
-        WorldState {
+        let mut world = WorldState {
             state,
+            names: HashMap::new(),
+            palette: BlockPalette::new(),
             id,
             ser_policy
+        };
+        if let StateSerializationPolicy::LoadAndSave {load_dir, ..}|StateSerializationPolicy::LoadOnly {load_dir} = &world.ser_policy {
+            let names = deserialize_block_names(load_dir, &world.id).unwrap_or_default();
+            world.update_all_names(names);
         }
+        world
     }
 
 
@@ -149,18 +240,29 @@ impl WorldState {
         format!("{}/state.txt", &basedir)
     }
 
+    fn names_filepath(dir: &str, id: &str) -> String {
+        format!("{}/{}/blocks.json", dir, id)
+    }
+
     pub fn update_all(&mut self, blocks: HashMap<Coord, Block>) {
         for (coord, block) in blocks {
             self.update_at(coord, block);
         }
     }
 
+    pub fn update_all_names(&mut self, names: Vec<(Coord, BlockIdentity)>) {
+        for (coord, identity) in names {
+            self.update_name(coord, Some((&identity.name, &identity.state)));
+        }
+    }
+
     fn try_serialize(&self)  {
         match &self.ser_policy {
             StateSerializationPolicy::LoadAndSave {  save_dir, .. }|
             StateSerializationPolicy::SaveOnly { save_dir } => {
                 let path = WorldState::state_filepath(save_dir.as_str(), &self.id, true);
                 self.serialize(path.as_str()).unwrap();
+                self.serialize_names(&WorldState::names_filepath(save_dir, &self.id)).unwrap();
             }
             StateSerializationPolicy::LoadOnly {..}|
             StateSerializationPolicy::None => {}
@@ -172,10 +274,7 @@ impl WorldState {
             StateSerializationPolicy::LoadAndSave { load_dir, ..}|
             StateSerializationPolicy::LoadOnly { load_dir} => {
                 let state_result = deserialize_worldstate(load_dir, id);
-                match state_result {
-                    Ok(state) => state,
-                    Err(_) => HashMap::new()
-                }
+                state_result.unwrap_or_default()
             },
             StateSerializationPolicy::SaveOnly { .. }|
             StateSerializationPolicy::None => { HashMap::new()}
@@ -187,7 +286,7 @@ impl WorldState {
         let (minv, maxv) = dimensions(self.state.keys());
         
         let mut file = std::fs::File::create(path)?;
-        write!(&mut file, "{}\n", 1)?;
+        writeln!(&mut file, "{}", 1)?;
         for y in minv.1..=maxv.1 {
             let start = Vec3::<i32>(minv.0, y, minv.2);
             let start_str = serde_json::to_string(&start)?;
@@ -200,23 +299,66 @@ impl WorldState {
 
     }
 
-    fn update_at(&mut self, loc_absolute: Coord, block: Block) {
+    /// Saves the block names in the format of the `blocks.json` of test worlds
+    fn serialize_names(&self, path: &str) -> Result<()> {
+        let entries: Vec<BlockEntry> = self.block_names().into_iter()
+            .map(|(pos, block)| BlockEntry {pos, block})
+            .collect();
+        std::fs::write(path, serde_json::to_string_pretty(&entries)?)?;
+        Ok(())
+    }
+
+    /// Returns true if the block changed
+    fn update_at(&mut self, loc_absolute: Coord, block: Block) -> bool {
         let previous = self.state.insert(loc_absolute, block.clone());
         match previous {
-            Some(ref oldblock) if oldblock==&block => {}, // do nothing
-            _ => self.try_serialize()
-        };
+            Some(ref oldblock) if oldblock==&block => false, // do nothing
+            _ => {
+                self.try_serialize();
+                true
+            }
+        }
     }
 
     pub fn get(&self, loc_absolute: &Coord) -> Block {
         self.state.get(loc_absolute).unwrap_or(&Block::Unknown).clone()
     }
 
+    /// Name and state of the block, if it has been inspected
+    pub fn identity(&self, loc_absolute: &Coord) -> Option<&BlockIdentity> {
+        self.names.get(loc_absolute).map(|key| self.palette.get(*key))
+    }
+
+    pub fn name_at(&self, loc_absolute: &Coord) -> Option<&str> {
+        self.identity(loc_absolute).map(|identity| identity.name.as_str())
+    }
+
+    /// Every known location of the block, e.g. `minecraft:diamond_ore`, in any state
+    pub fn positions_of(&self, name: &str) -> Vec<Coord> {
+        let keys: Vec<BlockKey> = self.palette.keys_of(name).collect();
+        let mut positions: Vec<Coord> = self.names.iter()
+            .filter(|(_, key)| keys.contains(key))
+            .map(|(coord, _)| coord.clone())
+            .collect();
+        positions.sort_by_key(|coord| (coord.0, coord.1, coord.2));
+        positions
+    }
+
+    /// Returns true if the name changed
+    fn update_name(&mut self, loc_absolute: Coord, identity: Option<(&str, &serde_json::Map<String, serde_json::Value>)>) -> bool {
+        let key = identity.map(|(name, state)| self.palette.intern(name, state));
+        let previous = match key {
+            Some(key) => self.names.insert(loc_absolute, key),
+            None => self.names.remove(&loc_absolute)
+        };
+        previous != key
+    }
+
     fn is_solid_above(&self, loc: &Coord) -> bool {
         let above = loc + &AxisDirection::AD_YP;
         match self.state.get(&above) {
-            Some(block) if matches!(block, Block::Unknown|Block::AirOrGravityBlock) => false, // don't know
-            Some(block) if matches!(block, Block::Block) => true,
+            Some(Block::Unknown|Block::AirOrGravityBlock) => false, // don't know
+            Some(Block::Block|Block::Bedrock) => true,
             Some(_) => {
                 self.is_solid_above(&above)
             },
@@ -224,48 +366,132 @@ impl WorldState {
         }
     }
 
-    pub fn update(&mut self, action: &TurtleAction, result: &TurtleActionReturn, loc: &LocationState) {
-        
-        if let Some(loc_absolute) = loc.loc_absolute.clone() {
-            match (action, result) {
-                (TurtleAction::Move{direction}, TurtleActionReturn::Success) 
-                    if matches!(direction, RelativeDirection::Forward|RelativeDirection::Backward|RelativeDirection::Up) => {
-                        self.update_at(loc_absolute, Block::Air);
-                        
-                    }
-                (TurtleAction::Move{direction}, TurtleActionReturn::Success)
-                    if matches!(direction, RelativeDirection::Down) => {
-                        let is_block_above = self.is_solid_above(&loc_absolute);
-                        if is_block_above {
-                            self.update_at(loc_absolute, Block::Air);
-                        } else {
-                            self.update_at(loc_absolute, Block::AirOrGravityBlock);
-                        }
-                    },
-                (TurtleAction::Move{direction}, TurtleActionReturn::Failure(_reason)) => {
-                    let unit_dir = loc.get_dest_direction_absolute(&direction).unwrap(); // has to exist since we are in absolute
-                    let dest = &loc_absolute + &unit_dir;
-                    self.update_at(dest, Block::Block);
-                },
-                (TurtleAction::Detect{direction}, TurtleActionReturn::Boolean(value)) => {
-                    let dest_loc = loc.get_dest_position_absolute(direction);
-                    let block = if *value {
-                        Block::Block
-                    } else {
-                        Block::Air
-                    };
-                    self.update_at(dest_loc.unwrap(), block);
-                },
-                (TurtleAction::Inspect{direction}, TurtleActionReturn::Failure(FailureReason::NoBlockToInspect)) => {
-
-                    let dest_loc = loc.get_dest_position_absolute(direction);
-                    self.update_at(dest_loc.unwrap(), Block::Air);
+    /// A solid block at the coordinate, keeping what is already known about it
+    fn solid_at(&self, coord: &Coord) -> Block {
+        match self.get(coord) {
+            Block::Bedrock => Block::Bedrock,
+            _ => Block::Block
+        }
+    }
+
+    /// A block the turtle can move into, keeping a known fluid since detect and dig don't see them
+    fn passable_at(&self, coord: &Coord) -> Block {
+        match self.get(coord) {
+            fluid @ (Block::Water|Block::Lava) => fluid,
+            _ => Block::Air
+        }
+    }
+
+    pub fn update(&mut self, action: &TurtleAction, result: &TurtleActionReturn, loc: &LocationState) -> WorldDiff {
+        let loc_absolute = match loc.loc_absolute.clone() {
+            Some(loc_absolute) => loc_absolute,
+            None => return vec![]
+        };
+        let change = match (action, result) {
+            (TurtleAction::Move{..}, TurtleActionReturn::Success) if self.get(&loc_absolute).is_fluid() => {
+                None // the turtle moved into a fluid, which stays a hazard
+            },
+            (TurtleAction::Move{direction: RelativeDirection::Forward|RelativeDirection::Backward|RelativeDirection::Up}, TurtleActionReturn::Success) => {
+                Some((loc_absolute, Block::Air))
+            },
+            (TurtleAction::Move{direction: RelativeDirection::Down}, TurtleActionReturn::Success) => {
+                let is_block_above = self.is_solid_above(&loc_absolute);
+                if is_block_above {
+                    Some((loc_absolute, Block::Air))
+                } else {
+                    Some((loc_absolute, Block::AirOrGravityBlock))
+                }
+            },
+            (TurtleAction::Move{direction}, TurtleActionReturn::Failure(FailureReason::MovementObstructed)) => {
+                // Other failures, like running out of fuel, say nothing about the destination
+                let unit_dir = loc.get_dest_direction_absolute(direction).unwrap(); // has to exist since we are in absolute
+                let dest = &loc_absolute + &unit_dir;
+                let block = match self.get(&dest) {
+                    // Space known to be free is more likely taken by a mob or another turtle, which
+                    // moves on. It's forgotten, and only becomes solid if the way is blocked again.
+                    Block::Air|Block::Water|Block::Lava => Block::Unknown,
+                    _ => self.solid_at(&dest)
+                };
+                Some((dest, block))
+            },
+            (TurtleAction::Detect{direction}, TurtleActionReturn::Boolean(value)) => {
+                let dest_loc = loc.get_dest_position_absolute(direction).unwrap();
+                let block = if *value {
+                    self.solid_at(&dest_loc)
+                } else {
+                    self.passable_at(&dest_loc)
+                };
+                Some((dest_loc, block))
+            },
+            (TurtleAction::Dig{direction}, TurtleActionReturn::Failure(FailureReason::NothingToDigHere)) => {
+                let dest_loc = loc.get_dest_position_absolute(direction).unwrap();
+                let block = self.passable_at(&dest_loc);
+                Some((dest_loc, block))
+            },
+            (TurtleAction::Inspect{direction}, TurtleActionReturn::Failure(FailureReason::NoBlockToInspect))|
+            (TurtleAction::Dig{direction}, TurtleActionReturn::Success) => {
+                let dest_loc = loc.get_dest_position_absolute(direction);
+                Some((dest_loc.unwrap(), Block::Air))
+            },
+            (TurtleAction::Inspect{direction}, TurtleActionReturn::InspectSuccess(name, _)) => {
+                let dest_loc = loc.get_dest_position_absolute(direction).unwrap();
+                let block = Block::fluid(name).unwrap_or_else(|| self.solid_at(&dest_loc));
+                Some((dest_loc, block))
+            },
+            (TurtleAction::Place{direction}, TurtleActionReturn::Success) => {
+                let dest_loc = loc.get_dest_position_absolute(direction).unwrap();
+                let block = self.solid_at(&dest_loc);
+                Some((dest_loc, block))
+            },
+            (TurtleAction::Attack{direction}, TurtleActionReturn::Success) => {
+                // There was something to hit, so the way is blocked by an entity rather than a block
+                let dest_loc = loc.get_dest_position_absolute(direction).unwrap();
+                match self.get(&dest_loc) {
+                    Block::Block => Some((dest_loc, Block::Unknown)),
+                    _ => None
                 }
-                _ => {}
+            },
+            (TurtleAction::Dig{direction}, TurtleActionReturn::Failure(FailureReason::UnbreakableBlockDetected)) => {
+                let dest_loc = loc.get_dest_position_absolute(direction);
+                Some((dest_loc.unwrap(), Block::Bedrock))
+            }
+            _ => None
+        };
+        // Only inspect tells what a block is, the name is forgotten once it's something else
+        let named = match (action, result, &change) {
+            (TurtleAction::Inspect{direction}, TurtleActionReturn::InspectSuccess(name, state), _) => {
+                let dest_loc = loc.get_dest_position_absolute(direction).unwrap();
+                self.update_name(dest_loc, Some((name, state)))
+            },
+            (TurtleAction::Place{..}, TurtleActionReturn::Success, Some((coord, _)))|
+            (_, _, Some((coord, Block::Air|Block::AirOrGravityBlock|Block::Unknown))) => self.update_name(coord.clone(), None),
+            _ => false
+        };
+        match change {
+            Some((coord, block)) if self.update_at(coord.clone(), block.clone()) => vec![(coord, block)],
+            _ => {
+                if named {
+                    self.try_serialize();
+                }
+                vec![]
             }
         }
     }
 
+    /// All known blocks, e.g. for recording the initial state
+    pub fn blocks(&self) -> WorldDiff {
+        self.state.iter().map(|(coord, block)| (coord.clone(), block.clone())).collect()
+    }
+
+    /// Names of all inspected blocks, ordered by their location
+    pub fn block_names(&self) -> Vec<(Coord, BlockIdentity)> {
+        let mut names: Vec<(Coord, BlockIdentity)> = self.names.iter()
+            .map(|(coord, key)| (coord.clone(), self.palette.get(*key).clone()))
+            .collect();
+        names.sort_by_key(|(coord, _)| (coord.0, coord.1, coord.2));
+        names
+    }
+
     pub fn to_ascii(&self, layer: i32) -> String {
         let mut result: String = String::new();
         let (minv, maxv) = dimensions(self.state.keys());
@@ -295,7 +521,7 @@ pub fn deserialize_worldstate(state_dir: &str, id: &str) -> Result<HashMap<Vec3<
     let path = WorldState::state_filepath(state_dir, id, false);
     let mut result: HashMap<Vec3<i32>, Block> = HashMap::new();
     // let file = std::fs::File::open(path)?;
-    println!("Opening path {}", &path);
+    report!(Debug, "Opening path {}", &path);
     let contents = std::fs::read_to_string(&path)?;
     let lines: Vec<&str> = contents.split('\n').collect();
     let version = lines.first().expect("Illegal file");
@@ -321,7 +547,7 @@ pub fn deserialize_worldstate(state_dir: &str, id: &str) -> Result<HashMap<Vec3<
 
                 let key = Vec3::<i32>(x, y, z);
                 let val = citer.next()
-                    .expect(format!("Coordinte {:?} did not exist in {}", &key, &path).as_str());
+                    .unwrap_or_else(|| panic!("Coordinte {:?} did not exist in {}", &key, &path));
                 result.insert(key, Block::from(val));
             }
         }
@@ -330,10 +556,22 @@ pub fn deserialize_worldstate(state_dir: &str, id: &str) -> Result<HashMap<Vec3<
     Ok(result)
 }
 
+pub fn deserialize_block_names(state_dir: &str, id: &str) -> Result<Vec<(Coord, BlockIdentity)>> {
+    let contents = std::fs::read_to_string(WorldState::names_filepath(state_dir, id))?;
+    let entries: Vec<BlockEntry> = serde_json::from_str(&contents)?;
+    Ok(entries.into_iter().map(|entry| (entry.pos, entry.block)).collect())
+}
+
 pub struct ActionHistory {
     history: Vec<(TurtleAction, TurtleActionReturn)>
 }
 
+impl Default for ActionHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ActionHistory  {
     pub fn new() -> Self {
         ActionHistory {
@@ -352,7 +590,7 @@ impl ActionHistory  {
     }
 
     pub fn update(&mut self, action: &TurtleAction, response: &TurtleActionReturn) {
-        self.history.push((action.clone(), response.clone()));
+        self.history.push((*action, response.clone()));
     }
 }
 
@@ -360,10 +598,79 @@ impl ActionHistory  {
 mod tests {
 
     use super::*;
+    use crate::location_state::LocationMode;
+    use crate::turtle_action::{detect, dig, fuel, go, inspect};
 
     #[test]
     fn test_world_state_loading() {
         WorldState::new("0".to_string(), StateSerializationPolicy::LoadOnly{load_dir:"state".to_string()});
         
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn only_obstructed_moves_record_blocks() {
+        let mut state = TurtleState::new("fuel_test".to_string(), StateSerializationPolicy::None);
+        state.location.loc_absolute = Some(Coord::zero());
+        state.location.location_precision = LocationMode::Absolute((Coord::zero(), Rotation::Y0));
+        let front = Coord::new(1, 0, 0);
+
+        assert!(state.update(&go::forward(), &TurtleActionReturn::Failure(FailureReason::OutOfFuel)).unwrap().is_empty());
+        assert_eq!(Block::Unknown, state.world.get(&front));
+        state.update(&fuel::refuel(), &TurtleActionReturn::Success).unwrap();
+        state.update(&go::forward(), &TurtleActionReturn::Success).unwrap();
+        assert_eq!(Block::Air, state.world.get(&front));
+
+        let diff = state.update(&go::forward(), &TurtleActionReturn::Failure(FailureReason::MovementObstructed)).unwrap();
+        assert_eq!(vec![(Coord::new(2, 0, 0), Block::Block)], diff);
+    }
+
+    #[test]
+    fn fluids_are_learned_from_inspect() {
+        let mut state = TurtleState::new("fluid_test".to_string(), StateSerializationPolicy::None);
+        state.location.loc_absolute = Some(Coord::zero());
+        let below = Coord::new(0, -1, 0);
+        let mut level = serde_json::Map::new();
+        level.insert("level".to_string(), serde_json::Value::from(0));
+
+        state.update(&detect::down(), &TurtleActionReturn::Boolean(false)).unwrap();
+        assert_eq!(Block::Air, state.world.get(&below));
+        let diff = state.update(&inspect::down(), &TurtleActionReturn::InspectSuccess("minecraft:lava".to_string(), level)).unwrap();
+        assert_eq!(vec![(below.clone(), Block::Lava)], diff);
+        assert!(state.world.is_hazard(&below));
+        assert_eq!(Some(false), state.world.is_obstructed(&below));
+
+        // Neither detect nor dig sees fluids, so they don't forget it
+        assert!(state.update(&detect::down(), &TurtleActionReturn::Boolean(false)).unwrap().is_empty());
+        assert!(state.update(&dig::down(), &TurtleActionReturn::Failure(FailureReason::NothingToDigHere)).unwrap().is_empty());
+        assert_eq!(Block::Lava, state.world.get(&below));
+    }
+
+    #[test]
+    fn block_names_are_learned_from_inspect() {
+        let dir = format!("{}/block_names_test", std::env::temp_dir().display());
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut state = TurtleState::new("names".to_string(), StateSerializationPolicy::SaveOnly {save_dir: dir.clone()});
+        state.location.loc_absolute = Some(Coord::zero());
+        let ore = "minecraft:diamond_ore".to_string();
+        let below = Coord::new(0, -1, 0);
+        let front = Coord::new(1, 0, 0);
+
+        state.update(&inspect::down(), &TurtleActionReturn::InspectSuccess(ore.clone(), serde_json::Map::new())).unwrap();
+        state.update(&inspect::forward(), &TurtleActionReturn::InspectSuccess(ore.clone(), serde_json::Map::new())).unwrap();
+        state.update(&inspect::up(), &TurtleActionReturn::InspectSuccess("minecraft:stone".to_string(), serde_json::Map::new())).unwrap();
+        assert_eq!(Some("minecraft:diamond_ore"), state.world.name_at(&below));
+        assert_eq!(vec![below.clone(), front.clone()], state.world.positions_of(&ore));
+        assert_eq!(Block::Block, state.world.get(&below));
+
+        // Detecting the ore again doesn't change what it is, digging it does
+        state.update(&detect::down(), &TurtleActionReturn::Boolean(true)).unwrap();
+        assert_eq!(Some("minecraft:diamond_ore"), state.world.name_at(&below));
+        state.update(&dig::down(), &TurtleActionReturn::Success).unwrap();
+        assert_eq!(None, state.world.identity(&below));
+        assert_eq!(vec![front.clone()], state.world.positions_of(&ore));
+
+        let loaded = WorldState::new("names".to_string(), StateSerializationPolicy::LoadOnly {load_dir: dir});
+        assert_eq!(vec![front], loaded.positions_of(&ore));
+        assert_eq!(Some("minecraft:stone"), loaded.name_at(&Coord::new(0, 1, 0)));
+    }
+}
@@ -18,7 +18,7 @@ pub struct Vec3<T>(pub T, pub T, pub T) where T: Vec3T<T>;
 
 impl<T> Vec3<T> where T: Vec3T<T> +  {
     pub fn from_array(arr: [T; 3]) -> Self {
-        Self{0: arr[0], 1:arr[1], 2:arr[2]}
+        Self(arr[0], arr[1], arr[2])
     }
     pub fn new(x: T, y: T, z: T) -> Self {
         Vec3::<T>(x,y,z)
@@ -98,7 +98,7 @@ impl<'a, T> SubAssign<&'a Vec3<T>> for Vec3<T> where T: Vec3T<T> {
     }
 }
 
-impl<'a, 'b, T> Sub<&'b Vec3<T>> for &'a Vec3<T> where T: Vec3T<T> {
+impl<'b, T> Sub<&'b Vec3<T>> for &Vec3<T> where T: Vec3T<T> {
     type Output = Vec3<T>;
 
     fn sub(self, rhs: &'b Vec3<T>) -> Self::Output {
@@ -106,7 +106,7 @@ impl<'a, 'b, T> Sub<&'b Vec3<T>> for &'a Vec3<T> where T: Vec3T<T> {
     }
 }
 
-impl<'a, 'b, T> Add<&'b Vec3<T>> for &'a Vec3<T> where T: Vec3T<T> {
+impl<'b, T> Add<&'b Vec3<T>> for &Vec3<T> where T: Vec3T<T> {
     type Output = Vec3<T>;
 
     fn add(self, rhs: &'b Vec3<T>) -> Self::Output {
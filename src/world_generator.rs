@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::{Map, Value};
+use crate::turtle_state::{Coord, StateSerializationPolicy, WorldState};
+use crate::block_palette::BlockIdentity;
+use crate::world_simulator::Runner;
+
+/// How much the surface rises and sinks around the average depth
+const TERRAIN_AMPLITUDE: i32 = 3;
+/// Distance between the random heights the surface is interpolated from
+const TERRAIN_GRID: i32 = 4;
+
+/// Ores with the highest level they generate at, as a fraction of the depth, the number of veins
+/// and the length of a vein
+const ORES: [(&str, f64, u32, u32); 5] = [
+    ("minecraft:coal_ore", 1.0, 8, 8),
+    ("minecraft:iron_ore", 0.7, 6, 6),
+    ("minecraft:gold_ore", 0.4, 3, 5),
+    ("minecraft:redstone_ore", 0.3, 3, 6),
+    ("minecraft:diamond_ore", 0.2, 2, 4)
+];
+
+/// Builds random worlds for the simulator. The same seed always gives the same world.
+///
+/// The world is a box around the x and z axes with a bedrock floor at y=0, stone with caves, ore
+/// veins, gravel pockets and lava lakes, and a grassy surface with trees. Its sides and ceiling are
+/// bedrock too, so the simulated turtle can't wander off to blocks that don't exist.
+#[derive(Debug, Clone)]
+pub struct WorldGenerator {
+    pub radius: i32, // the world spans -radius..=radius in x and z
+    pub depth: i32, // average height of the surface
+    pub sky: i32, // air above the highest surface
+    pub caves: u32,
+    pub gravel_pockets: u32,
+    pub lava_lakes: u32,
+    pub trees: u32
+}
+
+impl Default for WorldGenerator {
+    fn default() -> Self {
+        WorldGenerator {
+            radius: 8,
+            depth: 20,
+            sky: 8,
+            caves: 3,
+            gravel_pockets: 4,
+            lava_lakes: 1,
+            trees: 3
+        }
+    }
+}
+
+/// A generated world and where the turtle starts in it
+#[derive(Debug, Clone)]
+pub struct GeneratedWorld {
+    pub blocks: HashMap<Coord, Option<BlockIdentity>>, // None for air
+    pub spawn: Coord // air on the surface in the middle of the world
+}
+
+impl GeneratedWorld {
+    pub fn world_state(&self, id: &str) -> WorldState {
+        let mut world = WorldState::new(id.to_string(), StateSerializationPolicy::None);
+        world.update_all(self.blocks.iter().map(|(coord, block)| (coord.clone(), Runner::block_kind(block))).collect());
+        world
+    }
+
+    /// Number of the named blocks in the world
+    pub fn count(&self, name: &str) -> usize {
+        self.blocks.values().filter(|block| block.as_ref().map(|block| block.name.as_str()) == Some(name)).count()
+    }
+}
+
+impl WorldGenerator {
+    fn ceiling(&self) -> i32 {
+        self.depth + TERRAIN_AMPLITUDE + self.sky
+    }
+
+    pub fn generate(&self, seed: u64) -> GeneratedWorld {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut world = GeneratedWorld {blocks: HashMap::new(), spawn: Coord::zero()};
+        let heights = self.surface(&mut rng);
+        let r = self.radius;
+        for x in -r-1..=r+1 {
+            for z in -r-1..=r+1 {
+                for y in 0..=self.ceiling() + 1 {
+                    let bedrock = x.abs() > r || z.abs() > r || y == 0 || y > self.ceiling()
+                        || (y <= 2 && rng.gen_bool(0.5 / y as f64)); // the floor is uneven like in game
+                    let name = if bedrock {
+                        Some(Runner::BEDROCK)
+                    } else {
+                        let height = heights[&(x, z)];
+                        match height - y {
+                            d if d < 0 => None,
+                            0 => Some("minecraft:grass_block"),
+                            1..=3 => Some("minecraft:dirt"),
+                            _ => Some(Runner::DEFAULT_BLOCK)
+                        }
+                    };
+                    world.blocks.insert(Coord::new(x, y, z), name.map(BlockIdentity::new));
+                }
+            }
+        }
+
+        for (name, max_level, veins, length) in ORES.iter() {
+            let max_y = ((self.depth as f64 * max_level) as i32).max(2);
+            for _ in 0..*veins {
+                let start = self.random_coord(&mut rng, 1, max_y);
+                self.vein(&mut world, &mut rng, start, *length, name);
+            }
+        }
+        for _ in 0..self.gravel_pockets {
+            let center = self.random_coord(&mut rng, 3, self.depth - 2);
+            let radius = rng.gen_range(1, 3);
+            self.fill_ball(&mut world, &center, radius, radius, |block| block == Runner::DEFAULT_BLOCK, |_| Some(BlockIdentity::new("minecraft:gravel")));
+        }
+        for _ in 0..self.caves {
+            let start = self.random_coord(&mut rng, 3, self.depth - 2);
+            self.cave(&mut world, &mut rng, start);
+        }
+        for _ in 0..self.lava_lakes {
+            let center = self.random_coord(&mut rng, 3, (self.depth / 3).max(4));
+            // The bottom half is lava, the top half an air pocket above it
+            self.fill_ball(&mut world, &center, 3, 1, |block| block != Runner::BEDROCK,
+                |coord| if coord.1 <= center.1 {Some(BlockIdentity::fluid(Runner::LAVA, 0))} else {None});
+        }
+
+        world.spawn = Coord::new(0, heights[&(0, 0)] + 1, 0);
+        for _ in 0..self.trees {
+            let x = rng.gen_range(-r + 2, r - 1);
+            let z = rng.gen_range(-r + 2, r - 1);
+            let trunk = rng.gen_range(4, 7);
+            if x.abs() > 2 || z.abs() > 2 {
+                self.tree(&mut world, Coord::new(x, heights[&(x, z)], z), trunk);
+            }
+        }
+        world.blocks.insert(world.spawn.clone(), None);
+        world
+    }
+
+    /// Heights of the surface, interpolated from random heights on a coarse grid
+    fn surface(&self, rng: &mut StdRng) -> HashMap<(i32, i32), i32> {
+        let cells = (self.radius + 1) / TERRAIN_GRID + 1;
+        let mut grid = HashMap::new();
+        for i in -cells..=cells {
+            for j in -cells..=cells {
+                grid.insert((i, j), self.depth + rng.gen_range(-TERRAIN_AMPLITUDE, TERRAIN_AMPLITUDE + 1));
+            }
+        }
+        let mut heights = HashMap::new();
+        for x in -self.radius-1..=self.radius+1 {
+            for z in -self.radius-1..=self.radius+1 {
+                let (i, j) = (x.div_euclid(TERRAIN_GRID), z.div_euclid(TERRAIN_GRID));
+                let (fx, fz) = (x.rem_euclid(TERRAIN_GRID) as f64 / TERRAIN_GRID as f64, z.rem_euclid(TERRAIN_GRID) as f64 / TERRAIN_GRID as f64);
+                let corner = |di: i32, dj: i32| grid[&(i + di, j + dj)] as f64;
+                let height = corner(0, 0) * (1.0 - fx) * (1.0 - fz) + corner(1, 0) * fx * (1.0 - fz)
+                    + corner(0, 1) * (1.0 - fx) * fz + corner(1, 1) * fx * fz;
+                heights.insert((x, z), height.round() as i32);
+            }
+        }
+        heights
+    }
+
+    fn random_coord(&self, rng: &mut StdRng, min_y: i32, max_y: i32) -> Coord {
+        Coord::new(
+            rng.gen_range(-self.radius, self.radius + 1),
+            rng.gen_range(min_y, max_y.max(min_y) + 1),
+            rng.gen_range(-self.radius, self.radius + 1)
+        )
+    }
+
+    fn random_step(rng: &mut StdRng) -> Coord {
+        let mut step = Coord::zero();
+        let delta = if rng.gen_bool(0.5) {1} else {-1};
+        match rng.gen_range(0, 3) {
+            0 => step.0 = delta,
+            1 => step.1 = delta,
+            _ => step.2 = delta
+        }
+        step
+    }
+
+    fn inside(&self, coord: &Coord) -> bool {
+        coord.0.abs() <= self.radius && coord.2.abs() <= self.radius && coord.1 > 0 && coord.1 <= self.ceiling()
+    }
+
+    /// Replaces the blocks of the ball that pass the filter, the radii are horizontal and vertical
+    fn fill_ball(&self, world: &mut GeneratedWorld, center: &Coord, radius: i32, vertical: i32,
+                 replaces: impl Fn(&str) -> bool, block: impl Fn(&Coord) -> Option<BlockIdentity>) {
+        for x in -radius..=radius {
+            for y in -vertical..=vertical {
+                for z in -radius..=radius {
+                    let inside_ball = (x * x + z * z) as f64 / (radius * radius) as f64
+                        + (y * y) as f64 / (vertical * vertical).max(1) as f64 <= 1.0;
+                    let coord = center + &Coord::new(x, y, z);
+                    if !inside_ball || !self.inside(&coord) {
+                        continue;
+                    }
+                    let replaced = world.blocks.get(&coord).and_then(|block| block.as_ref()).is_some_and(|block| replaces(&block.name));
+                    if replaced {
+                        world.blocks.insert(coord.clone(), block(&coord));
+                    }
+                }
+            }
+        }
+    }
+
+    /// A random walk through stone, leaving ore behind
+    fn vein(&self, world: &mut GeneratedWorld, rng: &mut StdRng, start: Coord, length: u32, name: &str) {
+        let mut at = start;
+        for _ in 0..length {
+            let is_stone = matches!(world.blocks.get(&at), Some(Some(block)) if block.name == Runner::DEFAULT_BLOCK);
+            if is_stone && self.inside(&at) {
+                world.blocks.insert(at.clone(), Some(BlockIdentity::new(name)));
+            }
+            at = &at + &WorldGenerator::random_step(rng);
+        }
+    }
+
+    /// A winding tunnel, which may break through the surface
+    fn cave(&self, world: &mut GeneratedWorld, rng: &mut StdRng, start: Coord) {
+        let mut at = start;
+        for _ in 0..rng.gen_range(15, 40) {
+            self.fill_ball(world, &at, 1, 1, |block| block != Runner::BEDROCK, |_| None);
+            at = &at + &WorldGenerator::random_step(rng);
+            at.1 = at.1.max(3);
+        }
+    }
+
+    fn tree(&self, world: &mut GeneratedWorld, ground: Coord, trunk: i32) {
+        let is_grass = matches!(world.blocks.get(&ground), Some(Some(block)) if block.name == "minecraft:grass_block");
+        if !is_grass || ground.1 + trunk + 1 > self.ceiling() {
+            return;
+        }
+        let top = &ground + &Coord::new(0, trunk, 0);
+        for x in -2..=2 {
+            for y in -1..=1 {
+                for z in -2..=2 {
+                    let coord = &top + &Coord::new(x, y, z);
+                    let corner = x.abs() == 2 && z.abs() == 2;
+                    if !corner && self.inside(&coord) && world.blocks.get(&coord) == Some(&None) {
+                        world.blocks.insert(coord, Some(BlockIdentity::new("minecraft:oak_leaves")));
+                    }
+                }
+            }
+        }
+        let mut axis = Map::new();
+        axis.insert("axis".to_string(), Value::from("y"));
+        for y in 1..=trunk {
+            let log = BlockIdentity::with_state("minecraft:oak_log", axis.clone());
+            world.blocks.insert(&ground + &Coord::new(0, y, 0), Some(log));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turtle_state::Block;
+
+    #[test]
+    fn same_seed_gives_same_world() {
+        let generator = WorldGenerator::default();
+        let first = generator.generate(1);
+        let second = generator.generate(1);
+        assert_eq!(first.blocks, second.blocks);
+        assert_eq!(first.spawn, second.spawn);
+        assert_ne!(first.blocks, generator.generate(2).blocks);
+    }
+
+    #[test]
+    fn world_has_all_features() {
+        let generator = WorldGenerator::default();
+        let world = generator.generate(3);
+        for name in &["minecraft:coal_ore", "minecraft:iron_ore", "minecraft:gravel", "minecraft:lava",
+                "minecraft:oak_log", "minecraft:oak_leaves", "minecraft:grass_block", "minecraft:dirt"] {
+            assert!(world.count(name) > 0, "No {} in the world", name);
+        }
+        let state = world.world_state("generated");
+        assert_eq!(Block::Air, state.get(&world.spawn));
+        assert_eq!(Block::Bedrock, state.get(&Coord::new(0, 0, 0)));
+        assert_eq!(Block::Bedrock, state.get(&Coord::new(9, 5, 0)));
+        // Caves are air below the lowest surface
+        assert!(world.blocks.iter().any(|(coord, block)| coord.1 < generator.depth - TERRAIN_AMPLITUDE && block.is_none()));
+    }
+}
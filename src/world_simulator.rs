@@ -1,19 +1,157 @@
+use std::collections::{HashMap, HashSet};
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use crate::report;
+use crate::block_palette::{BlockEntry, BlockIdentity};
+use crate::faults::{Fault, FaultConfig, FaultInjector};
 use crate::location_state::{LocationMode, LocationState};
 use crate::turtle::Turtle;
+use crate::fuel_state::{self, FuelLevel, FuelState};
+use crate::inventory_state::{self, Confidence, DEFAULT_STACK_SIZE, InventoryState, Slot, SLOT_COUNT};
+use crate::run_history::{RunEvent, RunHistory};
+use crate::scenario::{Expectations, Region, Scenario};
+use crate::world_generator::WorldGenerator;
 use crate::turtle_action::{FailureReason, TurtleAction, TurtleActionReturn};
-use crate::turtle_program::TurtleProgram;
-use crate::turtle_rotation::AxisDirection;
-use crate::turtle_state::{ActionHistory, Coord, StateSerializationPolicy, TurtleState, WorldState};
+use crate::turtle_program::{NoProgram, TurtleProgram};
+use crate::turtle_rotation::{AxisDirection, RelativeDirection};
+use crate::turtle_state::{ActionHistory, Block, Coord, StateSerializationPolicy, TurtleState, WorldDiff, WorldState};
+
+pub const CHEST_SIZE: usize = 27;
+
+/// Items which can be equipped as upgrades
+const UPGRADES: [&str; 9] = [
+    "minecraft:diamond_pickaxe", "minecraft:diamond_axe", "minecraft:diamond_shovel", "minecraft:diamond_sword",
+    "minecraft:diamond_hoe", "minecraft:crafting_table", "computercraft:wireless_modem_normal",
+    "computercraft:wireless_modem_advanced", "computercraft:speaker"
+];
+
+/// A chest or another inventory block in the simulated world
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    pub slots: Vec<Slot>
+}
+
+impl Container {
+    pub fn new(size: usize) -> Self {
+        Container {
+            slots: vec![Slot::empty(Confidence::Observed); size]
+        }
+    }
+
+    /// Returns how many didn't fit
+    pub fn insert(&mut self, name: &str, count: u32) -> u32 {
+        let order: Vec<usize> = (0..self.slots.len()).collect();
+        inventory_state::store(&mut self.slots, &order, name, count)
+    }
+
+    pub fn space_for(&self, name: &str) -> u32 {
+        inventory_state::space_for(&self.slots, name)
+    }
+
+    /// The first non-empty slot, which is where the turtle sucks items from
+    pub fn first(&self) -> Option<&Slot> {
+        self.slots.iter().find(|slot| !slot.is_empty())
+    }
+
+    pub fn count_of(&self, name: &str) -> u32 {
+        self.slots.iter().filter(|slot| slot.name.as_deref() == Some(name)).filter_map(|slot| slot.count).sum()
+    }
+}
+
+/// A mob or another entity in the simulated world. It occupies a single cell and stays there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    pub name: String,
+    pub health: u32,
+    pub drops: Vec<(String, u32)> // collected by the turtle that kills it
+}
+
+impl Entity {
+    pub fn new(name: &str, health: u32, drops: &[(&str, u32)]) -> Self {
+        Entity {
+            name: name.to_string(),
+            health,
+            drops: drops.iter().map(|(name, count)| (name.to_string(), *count)).collect()
+        }
+    }
+}
+
+/// Limits of `Runner::run_with_limits`, None for no limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunLimits {
+    pub max_steps: Option<usize>,
+    pub max_fuel: Option<u32>, // one for every successful move
+    pub stuck_after: Option<usize> // how many times the same state may repeat
+}
+
+impl Default for RunLimits {
+    fn default() -> Self {
+        RunLimits {
+            max_steps: Some(100_000),
+            max_fuel: None,
+            stuck_after: Some(100)
+        }
+    }
+}
+
+/// An action of the program and how it went, the fault if the simulator injected one
+pub type Step = (TurtleAction, Result<TurtleActionReturn, Fault>);
+
+/// How a run of `Runner::run` ended. `steps` counts the actions executed, without the stop.
+#[derive(Debug, Clone, PartialEq)]
+#[must_use]
+pub enum RunOutcome {
+    Finished {steps: usize},
+    BudgetExceeded {steps: usize, fuel: u32},
+    /// The turtle kept coming back to the same location without learning anything new
+    Stuck {steps: usize, location: Coord, direction: AxisDirection},
+    /// The program or the turtle's state panicked. Panics of the simulator itself aren't caught.
+    Panicked {steps: usize, message: String},
+    /// The program returned an error instead of its next action
+    Failed {steps: usize, message: String}
+}
+
+impl RunOutcome {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, RunOutcome::Finished {..})
+    }
+}
 
 pub struct Runner {
     pub turtle: Turtle,
-    shadow_state: TurtleState
-    // ,shadow_location: Option<LocationState>
+    shadow_state: TurtleState,
+    block_names: HashMap<Coord, BlockIdentity>,
+    containers: HashMap<Coord, Container>,
+    items: HashMap<Coord, Vec<(String, u32)>>, // item entities lying in the world
+    entities: HashMap<Coord, Entity>,
+    faults: Option<FaultInjector>,
+    gps_region: Option<Region>, // None if gps works everywhere
+    expectations: Expectations,
+    occupied: HashSet<Coord>, // cells of other turtles in the same world
+    in_turtle: bool // the turtle's own code is running, to tell its panics from the simulator's
+}
+
+/// The parts of the shadow state which belong to one turtle rather than to the world, so that
+/// several turtles can take turns in the same `Runner`
+pub(crate) struct ShadowTurtle {
+    /// Always absolute, the simulator knows where every turtle is even if the turtle doesn't
+    pub location: LocationState,
+    pub fuel: FuelState,
+    pub inventory: InventoryState,
+    pub history: ActionHistory
 }
 
 
 impl Runner {
     const TEST_STATE_DIR: &'static str = "tests/state";
+    pub const DEFAULT_BLOCK: &'static str = "minecraft:stone";
+    pub const BEDROCK: &'static str = "minecraft:bedrock";
+    pub const CHEST_ITEM: &'static str = "minecraft:chest";
+    pub const WATER: &'static str = "minecraft:water";
+    pub const LAVA: &'static str = "minecraft:lava";
+    /// Damage of a single attack
+    pub const ATTACK_DAMAGE: u32 = 8;
 
     /// if load_state=true, the state is immediately loaded to WorldState. Otherwise it will only
     /// be used when executing commands, so that it will eventually be revealed.
@@ -27,39 +165,168 @@ impl Runner {
         };
 
         let shadow_wstate = WorldState::new(state_name.to_string(), load_from_test_policy);
+        let world = WorldState::new(state_name.to_string(), ser_policy);
+        let mut runner = Runner::with_worlds(state_name, shadow_wstate, world, start_location, start_location_known);
+        runner.block_names = Runner::load_block_names(state_name);
+        runner
+    }
 
+    fn with_worlds(id: &str, shadow_wstate: WorldState, world: WorldState, start_location: (Coord, AxisDirection), start_location_known: bool) -> Self {
+        let shadow_loc = Runner::start_location(&start_location);
+
+        let location = if start_location_known {
+            shadow_loc.clone()
+        } else {
+            LocationState::new()
+        };
+        let state = TurtleState::from(location, world);
+        let turtle = Turtle::from(id.to_string(), state);
+
+        let mut shadow_state = TurtleState::from(shadow_loc, shadow_wstate);
+        shadow_state.inventory = InventoryState::empty();
+        shadow_state.fuel = FuelState::unlimited();
+
+        Runner {
+            turtle,
+            shadow_state,
+            block_names: HashMap::new(),
+            containers: HashMap::new(),
+            items: HashMap::new(),
+            entities: HashMap::new(),
+            faults: None,
+            gps_region: None,
+            expectations: Expectations::default(),
+            occupied: HashSet::new(),
+            in_turtle: false
+        }
+    }
+
+    /// The absolute location of a turtle which starts at the given place
+    fn start_location(start_location: &(Coord, AxisDirection)) -> LocationState {
         let rotation = AxisDirection::dot(&LocationState::DEFAULT_DIRECTION, &start_location.1);
-        println!("Rotation: {:?}, {:?}", rotation, start_location.1);
-        let shadow_loc = LocationState {
+        report!(Debug, "Rotation: {:?}, {:?}", rotation, start_location.1);
+        LocationState {
             location_precision: LocationMode::Absolute((start_location.0.clone(), rotation)),
             loc: Coord::zero(),
             direction: LocationState::DEFAULT_DIRECTION,
             loc_absolute: Some(start_location.0.clone()),
-            direction_absolute: start_location.1,
+            direction_absolute: start_location.1.clone(),
             history: vec![]
-        };
-
+        }
+    }
 
+    /// Another turtle for the same world, with an empty inventory and unlimited fuel. The world
+    /// is unknown to it.
+    pub(crate) fn new_turtle(id: &str, start_location: (Coord, AxisDirection), start_location_known: bool) -> (Turtle, ShadowTurtle) {
+        let shadow_loc = Runner::start_location(&start_location);
         let location = if start_location_known {
             shadow_loc.clone()
         } else {
             LocationState::new()
         };
-        let world = WorldState::new(state_name.to_string(), ser_policy);
-        let state = TurtleState::from(location, world);
-        let turtle = Turtle::from(state_name.to_string(), state);
+        let world = WorldState::new(id.to_string(), StateSerializationPolicy::None);
+        let turtle = Turtle::from(id.to_string(), TurtleState::from(location, world));
+        let shadow = ShadowTurtle {
+            location: shadow_loc,
+            fuel: FuelState::unlimited(),
+            inventory: InventoryState::empty(),
+            history: ActionHistory::new()
+        };
+        (turtle, shadow)
+    }
 
-        let shadow_state = TurtleState::from(shadow_loc, shadow_wstate);
+    /// Exchanges the turtle of the runner, with its part of the shadow state
+    pub(crate) fn swap_turtle(&mut self, turtle: &mut Turtle, shadow: &mut ShadowTurtle) {
+        std::mem::swap(&mut self.turtle, turtle);
+        let state = &mut self.shadow_state;
+        std::mem::swap(&mut state.location, &mut shadow.location);
+        std::mem::swap(&mut state.fuel, &mut shadow.fuel);
+        std::mem::swap(&mut state.inventory, &mut shadow.inventory);
+        std::mem::swap(&mut state.history, &mut shadow.history);
+    }
 
+    /// Cells taken by other turtles, which obstruct moves and placing like entities do
+    pub(crate) fn set_occupied(&mut self, occupied: HashSet<Coord>) {
+        self.occupied = occupied;
+    }
 
-        let runner = Runner {
-            turtle,
-            shadow_state,
-        };
+    /// Whether an entity or another turtle is in the cell
+    fn is_occupied(&self, loc: &Coord) -> bool {
+        self.entities.contains_key(loc) || self.occupied.contains(loc)
+    }
 
+    /// Builds the shadow world from named blocks, None for air
+    fn with_blocks(id: &str, blocks: Vec<(Coord, Option<BlockIdentity>)>, start_location: (Coord, AxisDirection), start_location_known: bool, world_known: bool) -> Self {
+        let kinds: HashMap<Coord, Block> = blocks.iter().map(|(coord, block)| (coord.clone(), Runner::block_kind(block))).collect();
+        let mut shadow_wstate = WorldState::new(id.to_string(), StateSerializationPolicy::None);
+        shadow_wstate.update_all(kinds.clone());
+        let mut world = WorldState::new(id.to_string(), StateSerializationPolicy::None);
+        if world_known {
+            world.update_all(kinds);
+        }
+        let mut runner = Runner::with_worlds(id, shadow_wstate, world, start_location, start_location_known);
+        for (coord, block) in blocks {
+            if let Some(block) = block {
+                if block.name == Runner::CHEST_ITEM {
+                    runner.containers.insert(coord.clone(), Container::new(CHEST_SIZE));
+                }
+                runner.block_names.insert(coord, block);
+            }
+        }
         runner
     }
 
+    /// A world made by the generator, the turtle starts on its surface facing x+
+    pub fn generated(generator: &WorldGenerator, seed: u64, start_location_known: bool) -> Self {
+        let world = generator.generate(seed);
+        let blocks = world.blocks.into_iter().collect();
+        Runner::with_blocks(&format!("generated_{}", seed), blocks, (world.spawn, AxisDirection::Xp), start_location_known, false)
+    }
+
+    /// Loads the scenario of `tests/state/<name>/scenario.json`
+    pub fn from_scenario(name: &str) -> Result<Self> {
+        let path = format!("{}/{}/scenario.json", Runner::TEST_STATE_DIR, name);
+        Runner::with_scenario(name, &Scenario::from_file(&path)?)
+    }
+
+    pub fn with_scenario(id: &str, scenario: &Scenario) -> Result<Self> {
+        let start = &scenario.turtle;
+        let mut runner = Runner::with_blocks(id, scenario.blocks()?, (start.pos.clone(), start.direction.clone()),
+            start.location_known, start.world_known);
+        for chest in &scenario.chests {
+            let items: Vec<(&str, u32)> = chest.items.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+            runner.add_chest(chest.pos.clone(), &items);
+        }
+        for entity in &scenario.entities {
+            runner.add_entity(entity.pos.clone(), entity.entity());
+        }
+        for contents in &scenario.inventory {
+            if !(1..=SLOT_COUNT).contains(&contents.slot) {
+                return Err(anyhow!("Slot number {} out of range", contents.slot));
+            }
+            runner.set_slot(contents.slot, Slot::item(&contents.name, contents.count, Confidence::Observed));
+        }
+        if let Some(fuel) = &scenario.fuel {
+            runner.set_fuel(fuel.level, fuel.limit);
+        }
+        runner.gps_region = scenario.gps.clone();
+        runner.expectations = scenario.expect.clone();
+        Ok(runner)
+    }
+
+    /// Names from the `blocks.json` of a test world. Blocks without an entry are stone or bedrock.
+    fn load_block_names(state_name: &str) -> HashMap<Coord, BlockIdentity> {
+        let path = format!("{}/{}/blocks.json", Runner::TEST_STATE_DIR, state_name);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                let entries: Vec<BlockEntry> = serde_json::from_str(&contents)
+                    .unwrap_or_else(|e| panic!("Invalid block names in {}: {}", path, e));
+                entries.into_iter().map(|entry| (entry.pos, entry.block)).collect()
+            },
+            Err(_) => HashMap::new()
+        }
+    }
+
     pub fn make_world_unknown_loc_known(state_name: &str, start_loc: Coord, start_axis: AxisDirection) -> Self {
         Self::new(
             state_name,
@@ -103,18 +370,34 @@ impl Runner {
         match action {
             TurtleAction::Turn { .. } => TurtleActionReturn::Success,
             TurtleAction::Move { direction } => {
+                if self.shadow_state.fuel.level == FuelLevel::Known(0) {
+                    return TurtleActionReturn::Failure(FailureReason::OutOfFuel);
+                }
                 // Shadow location has to exist and be absolute
                 let dest_loc = self.shadow_location().get_dest_position_absolute(direction).unwrap();
+                if self.is_occupied(&dest_loc) {
+                    return TurtleActionReturn::Failure(FailureReason::MovementObstructed);
+                }
 
                 let obstructed = self.shadow_world().is_obstructed(&dest_loc);
-                println!("{:?} obstructed: {:?}", dest_loc, obstructed);
+                report!(Debug, "{:?} obstructed: {:?}", dest_loc, obstructed);
                 match obstructed {
                     Some(true) => TurtleActionReturn::Failure(FailureReason::MovementObstructed),
                     Some(false) => TurtleActionReturn::Success,
                     None => panic!("Moved to a block which can't be simulated due to missing information.")
                 }
             },
-            TurtleAction::Dig { .. } => {todo!()},
+            TurtleAction::Dig { direction } => {
+                match self.shadow_world().get(&self.shadow_dest(direction)) {
+                    Block::Air|
+                    Block::Water|
+                    Block::Lava => TurtleActionReturn::Failure(FailureReason::NothingToDigHere),
+                    Block::Bedrock => TurtleActionReturn::Failure(FailureReason::UnbreakableBlockDetected),
+                    Block::Block => TurtleActionReturn::Success,
+                    Block::Unknown|
+                    Block::AirOrGravityBlock => panic!("Dug a block which can't be simulated due to missing information.")
+                }
+            },
             TurtleAction::Detect { direction } => {
                 let dest_loc = self.shadow_location().get_dest_position_absolute(direction).unwrap();
                 let obstructed = self.shadow_world().is_obstructed(&dest_loc);
@@ -123,53 +406,447 @@ impl Runner {
                     None => panic!("Moved to a block which can't be simulated due to missing information.")
                 }
             },
-            TurtleAction::Place { .. } => {todo!()},
-            TurtleAction::Drop { .. } => {todo!()},
-            TurtleAction::Attack { .. } => {todo!()},
-            TurtleAction::Suck { .. } => {todo!()},
-            TurtleAction::Inspect { .. } => {todo!()},
-            TurtleAction::Compare { .. } => {todo!()},
-            TurtleAction::Select { .. } => {todo!()},
-            TurtleAction::ItemCount { .. } => {todo!()},
-            TurtleAction::ItemSpace { .. } => {todo!()},
-            TurtleAction::ItemDetail { .. } => {todo!()},
-            TurtleAction::TransferTo { .. } => {todo!()},
-            TurtleAction::CompareTo { .. } => {todo!()},
+            TurtleAction::Place { direction } => {
+                let dest_loc = self.shadow_dest(direction);
+                if self.shadow_selected_slot().is_empty() {
+                    return TurtleActionReturn::Failure(FailureReason::NoItemsToPlace);
+                }
+                if self.is_occupied(&dest_loc) {
+                    return TurtleActionReturn::Failure(FailureReason::CanNotPlaceBlockHere);
+                }
+                match self.shadow_world().is_obstructed(&dest_loc) {
+                    Some(false) => TurtleActionReturn::Success,
+                    Some(true) => TurtleActionReturn::Failure(FailureReason::CanNotPlaceBlockHere),
+                    None => panic!("Placed into a block which can't be simulated due to missing information.")
+                }
+            },
+            TurtleAction::Drop { direction, .. } => {
+                let selected = self.shadow_selected_slot();
+                match (&selected.name, self.containers.get(&self.shadow_dest(direction))) {
+                    _ if selected.is_empty() => TurtleActionReturn::Failure(FailureReason::NoItemsToDrop),
+                    (Some(name), Some(container)) if container.space_for(name) == 0 => TurtleActionReturn::Failure(FailureReason::NoSpaceForItems),
+                    _ => TurtleActionReturn::Success
+                }
+            },
+            TurtleAction::Attack { direction } => {
+                if self.entities.contains_key(&self.shadow_dest(direction)) {
+                    TurtleActionReturn::Success
+                } else {
+                    TurtleActionReturn::Failure(FailureReason::NothingToAttackHere)
+                }
+            },
+            TurtleAction::Suck { direction, .. } => {
+                match self.suckable(direction) {
+                    None => TurtleActionReturn::Failure(FailureReason::NoItemsToTake),
+                    Some(name) if self.shadow_state.inventory.space_for(&name) == 0 => TurtleActionReturn::Failure(FailureReason::NoSpaceForItems),
+                    Some(_) => TurtleActionReturn::Success
+                }
+            },
+            TurtleAction::Inspect { direction } => {
+                match self.shadow_block(&self.shadow_dest(direction)) {
+                    Some(block) => TurtleActionReturn::InspectSuccess(block.name, block.state),
+                    None => TurtleActionReturn::Failure(FailureReason::NoBlockToInspect)
+                }
+            },
+            TurtleAction::Compare { direction } => {
+                let selected = self.shadow_selected_slot();
+                // Fluids have no item, so they compare like air
+                let same = match self.shadow_block(&self.shadow_dest(direction)) {
+                    Some(block) if Block::fluid(&block.name).is_none() => selected.name.as_ref() == Some(&block.name),
+                    _ => selected.is_empty()
+                };
+                TurtleActionReturn::Boolean(same)
+            },
+            TurtleAction::Select { slot } => {
+                self.shadow_slot(*slot);
+                TurtleActionReturn::Boolean(true)
+            },
+            TurtleAction::ItemCount { slot } => TurtleActionReturn::Number(self.shadow_slot(*slot).count.unwrap()),
+            TurtleAction::ItemSpace { slot } => TurtleActionReturn::Number(self.shadow_slot(*slot).space().unwrap()),
+            TurtleAction::ItemDetail { slot, detailed } => {
+                let contents = self.shadow_slot(*slot);
+                match (&contents.name, contents.count) {
+                    (Some(name), Some(count)) if count > 0 => {
+                        let mut detail = Map::new();
+                        detail.insert("name".to_string(), Value::from(name.as_str()));
+                        detail.insert("count".to_string(), Value::from(count));
+                        detail.insert("damage".to_string(), Value::from(contents.damage.unwrap_or(0)));
+                        if *detailed {
+                            detail.insert("maxCount".to_string(), Value::from(contents.stack_size()));
+                        }
+                        TurtleActionReturn::DetailSuccess(detail)
+                    },
+                    _ => TurtleActionReturn::Failure(FailureReason::SlotIsEmpty)
+                }
+            },
+            TurtleAction::TransferTo { slot, .. } => {
+                let source = self.shadow_selected_slot();
+                let fits = match &source.name {
+                    Some(name) => self.shadow_slot(*slot).space_for(name).unwrap() > 0,
+                    None => true // nothing to transfer
+                };
+                if fits || self.shadow_state.inventory.selected == Some(*slot) {
+                    TurtleActionReturn::Success
+                } else {
+                    TurtleActionReturn::Failure(FailureReason::NoSpaceForItems)
+                }
+            },
+            TurtleAction::CompareTo { slot } => {
+                // Empty slots have no name, so two empty slots are equal
+                TurtleActionReturn::Boolean(self.shadow_selected_slot().name == self.shadow_state.inventory.slot(*slot).name)
+            },
             TurtleAction::GpsLocate { .. } => {
-                TurtleActionReturn::Coordinate(self.shadow_location().loc_absolute.as_ref().unwrap().clone())
+                let loc = self.shadow_location().loc_absolute.as_ref().unwrap();
+                match &self.gps_region {
+                    Some(region) if !region.contains(loc) => TurtleActionReturn::Failure(FailureReason::GpsLocateFailure),
+                    _ => TurtleActionReturn::Coordinate(loc.clone())
+                }
+            },
+            TurtleAction::Refuel { .. } => {
+                match &self.shadow_selected_slot().name {
+                    None => TurtleActionReturn::Failure(FailureReason::NoItemsToCombust),
+                    Some(name) if fuel_state::fuel_value(name).is_none() => TurtleActionReturn::Failure(FailureReason::ItemsNotCombustible),
+                    Some(_) => TurtleActionReturn::Success
+                }
             },
+            TurtleAction::GetFuelLevel => Runner::fuel_response(self.shadow_state.fuel.level),
+            TurtleAction::GetFuelLimit => Runner::fuel_response(self.shadow_state.fuel.limit),
+            TurtleAction::Equip { .. } => {
+                // An empty selected slot unequips the current upgrade
+                match &self.shadow_selected_slot().name {
+                    Some(name) if !UPGRADES.contains(&name.as_str()) => TurtleActionReturn::Failure(FailureReason::NotAValidUpgrade),
+                    _ => TurtleActionReturn::Success
+                }
+            },
+            // Crafting would need a crafting table upgrade and recipes
+
+            TurtleAction::Craft { .. } => TurtleActionReturn::Failure(FailureReason::NoMatchingRecipes),
+            TurtleAction::GetSelectedSlot => TurtleActionReturn::Number(self.shadow_state.inventory.selected.unwrap_or(1) as u32),
             TurtleAction::Stop => panic!()
         }
     }
 
+    fn fuel_response(value: FuelLevel) -> TurtleActionReturn {
+        match value {
+            FuelLevel::Known(x) => TurtleActionReturn::Number(x),
+            FuelLevel::Unlimited => TurtleActionReturn::Unlimited,
+            FuelLevel::Unknown => panic!("The simulated fuel level has to be known")
+        }
+    }
+
     pub fn set_program(&mut self, program: Box<dyn TurtleProgram>) {
         self.turtle.set_program(program);
     }
 
+    /// Executes the action without faults
     pub fn execute_action(&mut self, action: &TurtleAction) -> TurtleActionReturn {
         if action != &TurtleAction::Stop {
-            self.turtle.last_action = Some(action.clone());
-            let response = self.simulate_action(&action);
-            self.turtle.update( &response);
-            self.shadow_state.update(&action, &response);
+            let response = self.simulate_action(action);
+            self.respond(action, &response);
+            self.update_shadow(action, &response);
             response
         } else {
             TurtleActionReturn::Success
         }
     }
-    pub fn execute_next(&mut self) -> (TurtleAction, TurtleActionReturn) {
-        let action = self.turtle.next().unwrap_or(&TurtleAction::Stop).clone();
-        let response = self.execute_action(&action);
-        (action, response)
+
+    /// Answers an action chosen elsewhere, e.g. by the server for `FakeTurtle`. Only the shadow
+    /// state is updated, the turtle of the runner doesn't see it.
+    pub fn answer(&mut self, action: &TurtleAction) -> TurtleActionReturn {
+        let response = self.simulate_action(action);
+        self.update_shadow(action, &response);
+        response
+    }
+
+    fn respond(&mut self, action: &TurtleAction, response: &TurtleActionReturn) {
+        self.turtle.last_action = Some(*action);
+        self.in_turtle = true;
+        self.turtle.update(response).unwrap();
+        self.in_turtle = false;
+    }
+
+    /// Makes the following actions run into faults, decided by the seed
+    pub fn set_faults(&mut self, config: FaultConfig, seed: u64) {
+        self.faults = Some(FaultInjector::new(config, seed));
+    }
+
+    pub fn faults(&self) -> Option<&FaultInjector> {
+        self.faults.as_ref()
+    }
+
+    pub fn faults_mut(&mut self) -> Option<&mut FaultInjector> {
+        self.faults.as_mut()
+    }
+
+    /// Executes the action through the faults, if they are set. GPS timeouts and mobs are answered
+    /// like the real turtle would, while after a disconnect or a dropped response the turtle never
+    /// gets an answer.
+    pub fn try_execute_action(&mut self, action: &TurtleAction) -> Result<TurtleActionReturn, Fault> {
+        let mut faults = match self.faults.take() {
+            Some(faults) if action != &TurtleAction::Stop => faults,
+            faults => {
+                self.faults = faults;
+                return Ok(self.execute_action(action));
+            }
+        };
+        let result = self.execute_with_faults(action, &mut faults);
+        faults.tick();
+        self.faults = Some(faults);
+        result
     }
 
-    /// Runs an entire program from start to finish
-    pub fn run(&mut self, program: Box<dyn TurtleProgram>) {
+    fn execute_with_faults(&mut self, action: &TurtleAction, faults: &mut FaultInjector) -> Result<TurtleActionReturn, Fault> {
+        // Mobs only appear in free space
+        let dest = match action {
+            TurtleAction::Move { direction } => Some(self.shadow_dest(direction))
+                .filter(|dest| self.shadow_world().is_obstructed(dest) == Some(false)),
+            _ => None
+        };
+        match faults.before_action(action, dest.as_ref()) {
+            Some(Fault::Disconnect) => {
+                self.reconnect();
+                Err(Fault::Disconnect)
+            },
+            None if matches!(action, TurtleAction::Attack { direction } if faults.kill_mob(&self.shadow_dest(direction))) => {
+                // The mobs of the faults die from a single hit
+                let response = TurtleActionReturn::Success;
+                self.respond(action, &response);
+                self.shadow_state.history.update(action, &response);
+                Ok(response)
+            },
+            Some(fault) => {
+                // Nothing happens in the world, but the turtle sees a failure
+                let reason = match fault {
+                    Fault::GpsTimeout => FailureReason::GpsLocateFailure,
+                    _ => FailureReason::MovementObstructed
+                };
+                let response = TurtleActionReturn::Failure(reason);
+                self.respond(action, &response);
+                self.shadow_state.history.update(action, &response);
+                Ok(response)
+            },
+            None => {
+                let response = self.simulate_action(action);
+                let fault = faults.after_action();
+                if fault.is_none() {
+                    self.respond(action, &response);
+                }
+                self.update_shadow(action, &response);
+                fault.map_or(Ok(response), Err)
+            }
+        }
+    }
+
+    /// Connects the turtle again, which gives it a new state like the server does. The known world
+    /// is kept as if it was saved, and the program carries on.
+    fn reconnect(&mut self) {
+        let id = self.turtle.id.clone();
+        let world = std::mem::replace(&mut self.turtle.state.world, WorldState::new(id.clone(), StateSerializationPolicy::None));
+        let program = std::mem::replace(&mut self.turtle.program, Box::new(NoProgram {}));
+        self.turtle = Turtle::from(id, TurtleState::from(LocationState::new(), world));
+        self.turtle.program = program;
+    }
+    /// Applies the effects of the action to the shadow state. The shadow inventory is the truth,
+    /// so the items gained are decided here rather than inferred.
+    fn update_shadow(&mut self, action: &TurtleAction, response: &TurtleActionReturn) {
+        let start = self.shadow_location().loc_absolute.clone().unwrap();
+        match (action, response) {
+            (TurtleAction::Dig { direction }, TurtleActionReturn::Success) => {
+                let dest_loc = self.shadow_dest(direction);
+                let dug = inventory_state::dropped_item(&self.shadow_block(&dest_loc).unwrap().name).to_string();
+                self.block_names.remove(&dest_loc);
+                if let Some(container) = self.containers.remove(&dest_loc) {
+                    for slot in container.slots {
+                        if let (Some(name), Some(count)) = (slot.name, slot.count) {
+                            self.drop_item(dest_loc.clone(), name, count);
+                        }
+                    }
+                }
+                self.collect(dug, 1);
+            },
+            (TurtleAction::Attack { direction }, TurtleActionReturn::Success) => {
+                let dest_loc = self.shadow_dest(direction);
+                let entity = self.entities.get_mut(&dest_loc).unwrap();
+                entity.health = entity.health.saturating_sub(Runner::ATTACK_DAMAGE);
+                if entity.health == 0 {
+                    let entity = self.entities.remove(&dest_loc).unwrap();
+                    for (name, count) in entity.drops {
+                        self.collect(name, count);
+                    }
+                }
+            },
+            (TurtleAction::Place { direction }, TurtleActionReturn::Success) => {
+                let dest_loc = self.shadow_dest(direction);
+                if let Some((name, _)) = self.shadow_selected_slot_mut().take(1) {
+                    if name == Runner::CHEST_ITEM {
+                        self.containers.insert(dest_loc.clone(), Container::new(CHEST_SIZE));
+                    }
+                    self.block_names.insert(dest_loc, BlockIdentity::new(&name));
+                }
+            },
+            (TurtleAction::Drop { direction, count }, TurtleActionReturn::Success) => {
+                let dest_loc = self.shadow_dest(direction);
+                let selected = self.shadow_selected_slot();
+                let mut limit = count.unwrap_or(DEFAULT_STACK_SIZE);
+                if let (Some(name), Some(container)) = (&selected.name, self.containers.get(&dest_loc)) {
+                    limit = limit.min(container.space_for(name));
+                }
+                if let Some((name, count)) = self.shadow_selected_slot_mut().take(limit) {
+                    match self.containers.get_mut(&dest_loc) {
+                        Some(container) => {
+                            container.insert(&name, count);
+                        },
+                        None => self.drop_item(dest_loc, name, count)
+                    }
+                }
+            },
+            (TurtleAction::Suck { direction, count }, TurtleActionReturn::Success) => {
+                let dest_loc = self.shadow_dest(direction);
+                let name = self.suckable(direction).unwrap();
+                let limit = count.unwrap_or(DEFAULT_STACK_SIZE).min(self.shadow_state.inventory.space_for(&name));
+                let taken = match self.containers.get_mut(&dest_loc) {
+                    Some(container) => container.slots.iter_mut().find(|slot| !slot.is_empty()).and_then(|slot| slot.take(limit)),
+                    None => {
+                        let entities = self.items.get_mut(&dest_loc).unwrap();
+                        let (name, available) = entities.remove(0);
+                        if available > limit {
+                            entities.insert(0, (name.clone(), available - limit));
+                        }
+                        if entities.is_empty() {
+                            self.items.remove(&dest_loc);
+                        }
+                        Some((name, available.min(limit)))
+                    }
+                };
+                if let Some((name, count)) = taken {
+                    self.shadow_state.inventory.insert(&name, count);
+                }
+            },
+            (TurtleAction::Refuel { count }, TurtleActionReturn::Success) => {
+                // Like in game, refuel(0) only tests whether the selected item burns
+                let limit = count.unwrap_or(u32::MAX);
+                if let Some((name, burned)) = self.shadow_selected_slot_mut().take(limit) {
+                    let fuel = &mut self.shadow_state.fuel;
+                    if let (FuelLevel::Known(level), FuelLevel::Known(max)) = (fuel.level, fuel.limit) {
+                        let gained = fuel_state::fuel_value(&name).unwrap() * burned;
+                        fuel.level = FuelLevel::Known(level.saturating_add(gained).min(max));
+                    }
+                    if name == "minecraft:lava_bucket" {
+                        self.shadow_state.inventory.insert("minecraft:bucket", burned);
+                    }
+                }
+            },
+            (TurtleAction::Select { slot }, TurtleActionReturn::Boolean(true)) => {
+                self.shadow_state.inventory.selected = Some(*slot);
+            },
+            (TurtleAction::TransferTo { slot, count }, TurtleActionReturn::Success) => {
+                let source = self.shadow_selected_slot();
+                if let Some(name) = &source.name {
+                    let limit = count.unwrap_or(u32::MAX).min(self.shadow_slot(*slot).space_for(name).unwrap());
+                    if self.shadow_state.inventory.selected != Some(*slot) {
+                        if let Some((name, count)) = self.shadow_selected_slot_mut().take(limit) {
+                            inventory_state::store(&mut self.shadow_state.inventory.slots, &[*slot as usize - 1], &name, count);
+                        }
+                    }
+                }
+            },
+            (TurtleAction::Equip { direction }, TurtleActionReturn::Success) => {
+                let new = match self.shadow_selected_slot_mut().take(1) {
+                    Some((name, _)) => Slot::item(&name, 1, Confidence::Observed),
+                    None => Slot::empty(Confidence::Observed)
+                };
+                let inventory = &mut self.shadow_state.inventory;
+                let equipped = match direction {
+                    RelativeDirection::Left => &mut inventory.left,
+                    _ => &mut inventory.right
+                };
+                let previous = std::mem::replace(equipped, new);
+                if let (Some(name), Some(count)) = (previous.name, previous.count) {
+                    inventory.insert(&name, count);
+                }
+            },
+            // The shadow inventory is the truth, it's not changed by observations
+            _ => {}
+        }
+        let state = &mut self.shadow_state;
+        state.location.update(action, response).unwrap();
+        // The shadow world is the truth, a failed action leaves it as it was. This also keeps an
+        // entity or turtle in the way from becoming a block.
+        if !matches!(response, TurtleActionReturn::Failure(_)) {
+            state.world.update(action, response, &state.location);
+        }
+        if !matches!(action, TurtleAction::Refuel { .. }) {
+            state.fuel.update(action, response);
+        }
+        state.history.update(action, response);
+
+        // Gravity blocks fall into the space that was just emptied
+        let emptied = match (action, response) {
+            (TurtleAction::Dig { direction }, TurtleActionReturn::Success) => Some(self.shadow_dest(direction)),
+            (TurtleAction::Move { .. }, TurtleActionReturn::Success) => Some(start),
+            _ => None
+        };
+        if let Some(loc) = emptied {
+            if matches!(action, TurtleAction::Move { .. }) {
+                self.leave_fluid(&loc);
+            }
+            self.settle_above(loc);
+        }
+    }
+
+    /// Executes the next action of the program, an error if the program has none to give
+    pub fn execute_next(&mut self) -> Result<Step> {
+        self.in_turtle = true;
+        let action = self.turtle.next_action().copied();
+        self.in_turtle = false;
+        let action = action?;
+        let response = self.try_execute_action(&action);
+        Ok((action, response))
+    }
+
+    /// Runs an entire program from start to finish, within the default limits
+    pub fn run(&mut self, program: Box<dyn TurtleProgram>) -> RunOutcome {
+        self.run_with_limits(program, &RunLimits::default())
+    }
+
+    /// Runs the program until it stops, runs out of budget, gets stuck, fails or panics
+    pub fn run_with_limits(&mut self, program: Box<dyn TurtleProgram>, limits: &RunLimits) -> RunOutcome {
         self.set_program(program);
+        let mut steps = 0;
+        let mut fuel = 0;
+        let mut seen: HashMap<(Coord, AxisDirection, usize, u32), usize> = HashMap::new();
         loop {
-            let (action, _response) = self.execute_next();
+            if limits.max_steps.is_some_and(|max| steps >= max) || limits.max_fuel.is_some_and(|max| fuel >= max) {
+                return RunOutcome::BudgetExceeded {steps, fuel};
+            }
+            self.in_turtle = false;
+            let next = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.execute_next()));
+            let (action, response) = match next {
+                Ok(Ok(next)) => next,
+                Ok(Err(err)) => return RunOutcome::Failed {steps, message: err.to_string()},
+                Err(payload) if !self.in_turtle => std::panic::resume_unwind(payload),
+                Err(payload) => {
+                    let message = payload.downcast_ref::<&str>().map(|x| x.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    return RunOutcome::Panicked {steps, message};
+                }
+            };
             if action == TurtleAction::Stop {
-                break;
+                return RunOutcome::Finished {steps};
+            }
+            steps += 1;
+            if matches!(action, TurtleAction::Move {..}) && response == Ok(TurtleActionReturn::Success) {
+                fuel += 1;
+            }
+            // Coming back to the same place without learning anything or gaining items
+            let location = self.shadow_location();
+            let items = self.shadow_state.inventory.slots.iter().filter_map(|slot| slot.count).sum();
+            let key = (location.loc_absolute.clone().unwrap(), location.direction_absolute.clone(), self.world().state.len(), items);
+            let visits = seen.entry(key.clone()).or_insert(0);
+            *visits += 1;
+            if limits.stuck_after.is_some_and(|max| *visits > max) {
+                return RunOutcome::Stuck {steps, location: key.0, direction: key.1};
             }
         }
     }
@@ -186,6 +863,188 @@ impl Runner {
         &self.shadow_state.world
     }
 
+    fn shadow_selected_slot(&self) -> &Slot {
+        let inventory = &self.shadow_state.inventory;
+        inventory.slot(inventory.selected.unwrap_or(1))
+    }
+
+    fn shadow_slot(&self, slot: u8) -> &Slot {
+        if !(1..=SLOT_COUNT).contains(&slot) {
+            panic!("Slot number {} out of range", slot);
+        }
+        self.shadow_state.inventory.slot(slot)
+    }
+
+    /// Makes the simulated turtle use fuel, by default it has unlimited fuel
+    pub fn set_fuel(&mut self, level: u32, limit: u32) {
+        self.shadow_state.fuel = FuelState {level: FuelLevel::Known(level.min(limit)), limit: FuelLevel::Known(limit)};
+    }
+
+    pub fn shadow_fuel(&self) -> &FuelState {
+        &self.shadow_state.fuel
+    }
+
+    /// Sets the contents of a slot of the simulated turtle
+    pub fn set_slot(&mut self, slot: u8, contents: Slot) {
+        self.shadow_slot(slot);
+        self.shadow_state.inventory.slots[slot as usize - 1] = Slot {confidence: Confidence::Observed, ..contents};
+    }
+
+    fn shadow_selected_slot_mut(&mut self) -> &mut Slot {
+        let inventory = &mut self.shadow_state.inventory;
+        let selected = inventory.selected.unwrap_or(1) as usize;
+        &mut inventory.slots[selected - 1]
+    }
+
+    fn shadow_dest(&self, direction: &RelativeDirection) -> Coord {
+        // Shadow location has to exist and be absolute
+        self.shadow_location().get_dest_position_absolute(direction).unwrap()
+    }
+
+    /// Name of the item the turtle would suck from the given direction
+    fn suckable(&self, direction: &RelativeDirection) -> Option<String> {
+        let dest_loc = self.shadow_dest(direction);
+        match self.containers.get(&dest_loc) {
+            Some(container) => container.first().and_then(|slot| slot.name.clone()),
+            None => self.items.get(&dest_loc).and_then(|entities| entities.first()).map(|(name, _)| name.clone())
+        }
+    }
+
+    /// Puts the items into the inventory, the ones that don't fit drop on the ground
+    fn collect(&mut self, name: String, count: u32) {
+        let left = self.shadow_state.inventory.insert(&name, count);
+        if left > 0 {
+            let here = self.shadow_location().loc_absolute.clone().unwrap();
+            self.drop_item(here, name, left);
+        }
+    }
+
+    /// Items dropped into lava burn
+    fn drop_item(&mut self, loc: Coord, name: String, count: u32) {
+        if self.shadow_world().get(&loc) != Block::Lava {
+            self.items.entry(loc).or_default().push((name, count));
+        }
+    }
+
+    /// A lava source is displaced by the turtle moving through it. Water sources only waterlog
+    /// the turtle, and flowing fluid flows back after it.
+    fn leave_fluid(&mut self, loc: &Coord) {
+        if self.shadow_world().get(loc) == Block::Lava && self.shadow_block(loc).unwrap().is_source() {
+            self.set_shadow_block(loc.clone(), None);
+        }
+    }
+
+    /// Places a chest with the given contents into the shadow world
+    pub fn add_chest(&mut self, loc: Coord, contents: &[(&str, u32)]) {
+        let mut container = Container::new(CHEST_SIZE);
+        for (name, count) in contents {
+            container.insert(name, *count);
+        }
+        self.add_block(loc.clone(), BlockIdentity::new(Runner::CHEST_ITEM));
+        self.containers.insert(loc, container);
+    }
+
+    /// Places a named block into the shadow world, fluids are recognized by their name
+    pub fn add_block(&mut self, loc: Coord, block: BlockIdentity) {
+        self.set_shadow_block(loc, Some(block));
+    }
+
+    /// Kind of the named block in the world state, None for air
+    pub(crate) fn block_kind(block: &Option<BlockIdentity>) -> Block {
+        match block {
+            Some(block) if block.name == Runner::BEDROCK => Block::Bedrock,
+            Some(block) => Block::fluid(&block.name).unwrap_or(Block::Block),
+            None => Block::Air
+        }
+    }
+
+    /// Sets a block of the shadow world, None for air
+    fn set_shadow_block(&mut self, loc: Coord, block: Option<BlockIdentity>) {
+        let kind = Runner::block_kind(&block);
+        self.shadow_state.world.update_all(vec![(loc.clone(), kind)].into_iter().collect());
+        match block {
+            Some(block) => self.block_names.insert(loc, block),
+            None => self.block_names.remove(&loc)
+        };
+    }
+
+    /// Lets the gravity blocks stacked above an emptied location fall as far as they can
+    fn settle_above(&mut self, loc: Coord) {
+        let turtle = self.shadow_location().loc_absolute.clone().unwrap();
+        let mut above = &loc + &AxisDirection::AD_YP;
+        while self.shadow_world().get(&above) == Block::Block {
+            let block = self.shadow_block(&above).unwrap();
+            if !block.falls() {
+                break;
+            }
+            let mut to = above.clone();
+            loop {
+                let below = &to + &AxisDirection::AD_YM;
+                if self.shadow_world().is_obstructed(&below) != Some(false) || below == turtle || self.occupied.contains(&below) {
+                    break;
+                }
+                to = below;
+            }
+            if to == above {
+                break;
+            }
+            self.set_shadow_block(above.clone(), None);
+            self.set_shadow_block(to, Some(block));
+            above = &above + &AxisDirection::AD_YP;
+        }
+    }
+
+    /// The block at the location in the shadow world, None for air
+    pub fn shadow_block(&self, loc: &Coord) -> Option<BlockIdentity> {
+        let default = match self.shadow_world().get(loc) {
+            Block::Air => return None,
+            Block::Block => BlockIdentity::new(Runner::DEFAULT_BLOCK),
+            Block::Bedrock => BlockIdentity::new(Runner::BEDROCK),
+            Block::Water => BlockIdentity::fluid(Runner::WATER, 0),
+            Block::Lava => BlockIdentity::fluid(Runner::LAVA, 0),
+            Block::Unknown|
+            Block::AirOrGravityBlock => panic!("Block at {:?} can't be simulated due to missing information.", loc)
+        };
+        Some(self.block_names.get(loc).cloned().unwrap_or(default))
+    }
+
+    /// Gives items to the simulated turtle
+    pub fn add_items(&mut self, name: &str, count: u32) {
+        self.shadow_state.inventory.insert(name, count);
+    }
+
+    /// Puts an entity into the shadow world, it blocks the turtle until killed
+    pub fn add_entity(&mut self, loc: Coord, entity: Entity) {
+        self.entities.insert(loc, entity);
+    }
+
+    pub fn entity_at(&self, loc: &Coord) -> Option<&Entity> {
+        self.entities.get(loc)
+    }
+
+    /// Limits gps to the region, None makes it work everywhere
+    pub fn set_gps_region(&mut self, region: Option<Region>) {
+        self.gps_region = region;
+    }
+
+    /// Checks the expected end conditions of the scenario, if the runner was made from one
+    pub fn check_expectations(&self) -> Result<()> {
+        self.expectations.check(self)
+    }
+
+    pub fn container(&self, loc: &Coord) -> Option<&Container> {
+        self.containers.get(loc)
+    }
+
+    /// Item entities lying at the given location
+    pub fn items_at(&self, loc: &Coord) -> &[(String, u32)] {
+        self.items.get(loc).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn shadow_inventory(&self) -> &InventoryState {
+        &self.shadow_state.inventory
+    }
+
     pub fn shadow_location(&self) -> &LocationState {
         &self.shadow_state.location
     }
@@ -197,11 +1056,110 @@ impl Runner {
 }
 
 
+#[derive(Debug, Error)]
+pub enum DivergenceKind {
+    #[error("the update failed: {0}")]
+    UpdateFailed(String),
+    #[error("expected location {expected:?}, got {actual:?}")]
+    Location {expected: Box<LocationState>, actual: Box<LocationState>},
+    #[error("expected blocks {expected:?}, got {actual:?}")]
+    World {expected: WorldDiff, actual: WorldDiff}
+}
+
+/// The first point where a replayed run no longer matches the recording.
+#[derive(Debug, Error)]
+#[error("Run diverged at step {step} ({action:?}): {kind}")]
+pub struct Divergence {
+    pub step: usize, // index of the action, starting from 1
+    pub action: TurtleAction,
+    pub kind: DivergenceKind
+}
+
+/// Feeds a run recorded by `RunHistory` back through a fresh `Turtle` and compares the
+/// reconstructed state against the recording after every action. Only the location and the
+/// blocks are compared: the action events don't record block names or the inventory, so those
+/// are taken from the initial state and then left to the replayed updates.
+pub struct Replayer {
+    pub turtle: Turtle,
+    events: Vec<RunEvent>,
+    expected_world: HashMap<Coord, Block>,
+    step: usize
+}
+
+impl Replayer {
+    pub fn from_file(path: &str) -> Result<Self> {
+        Replayer::new(RunHistory::read(path)?)
+    }
+
+    pub fn new(events: Vec<RunEvent>) -> Result<Self> {
+        let (location, world, names, inventory) = match events.first() {
+            Some(RunEvent::Initial {location, world, names, inventory}) => (location.clone(), world.clone(), names.clone(), inventory.clone()),
+            _ => return Err(anyhow!("Run does not start with the initial state"))
+        };
+        let expected_world: HashMap<Coord, Block> = world.into_iter().collect();
+        let mut world_state = WorldState::new("replay".to_string(), StateSerializationPolicy::None);
+        world_state.update_all(expected_world.clone());
+        world_state.update_all_names(names);
+        let mut state = TurtleState::from(location, world_state);
+        state.inventory = inventory;
+        Ok(Replayer {
+            turtle: Turtle::from("replay".to_string(), state),
+            events,
+            expected_world,
+            step: 0
+        })
+    }
+
+    fn same_location(a: &LocationState, b: &LocationState) -> bool {
+        a.loc == b.loc && a.loc_absolute == b.loc_absolute && a.direction == b.direction
+            && a.direction_absolute == b.direction_absolute && a.location_precision == b.location_precision
+    }
+
+    /// Replays the next action, returns None when the run has ended.
+    pub fn step(&mut self) -> Option<Result<(), Divergence>> {
+        self.step += 1;
+        let (action, result, location, world) = match self.events.get(self.step)? {
+            RunEvent::Action {action, result, location, world} => (*action, result.clone(), location.clone(), world.clone()),
+            RunEvent::Initial {..} => return Some(Ok(())) // only expected first, nothing to compare
+        };
+        let step = self.step;
+        let diverged = |kind| Some(Err(Divergence {step, action, kind}));
+
+        self.turtle.last_action = Some(action);
+        if let Err(e) = self.turtle.update(&result) {
+            return diverged(DivergenceKind::UpdateFailed(e.to_string()));
+        }
+        if !Replayer::same_location(&location, &self.turtle.state.location) {
+            return diverged(DivergenceKind::Location {expected: Box::new(location), actual: Box::new(self.turtle.state.location.clone())});
+        }
+        self.expected_world.extend(world);
+        let actual_world = &self.turtle.state.world.state;
+        if &self.expected_world != actual_world {
+            let differs = |a: &HashMap<Coord, Block>, b: &HashMap<Coord, Block>| -> WorldDiff {
+                a.iter().filter(|(coord, block)| b.get(coord) != Some(block)).map(|(c, b)| (c.clone(), b.clone())).collect()
+            };
+            let expected = differs(&self.expected_world, actual_world);
+            let actual = differs(actual_world, &self.expected_world);
+            return diverged(DivergenceKind::World {expected, actual});
+        }
+        Some(Ok(()))
+    }
+
+    /// Replays the whole run, stopping at the first divergence.
+    pub fn run(&mut self) -> Result<(), Divergence> {
+        while let Some(result) = self.step() {
+            result?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::turtle_program::FromActionsProgram;
-    use crate::turtle_action::{turn, go, detect, gps};
+    use crate::turtle_action::{turn, go, detect, dig, gps, fuel, drop, suck, inventory, place, inspect, compare, equip, attack};
+    use serde_json::json;
     use crate::turtle_state::Block;
 
     #[test]
@@ -247,13 +1205,13 @@ mod tests {
         );
         let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
         assert_eq!(Coord::zero(), runner.location().loc);
-        runner.run(Box::new(program));
+        assert!(runner.run(Box::new(program)).is_finished());
         assert_eq!(Coord::new(1,0,0), runner.location().loc);
         assert_eq!(Coord::new(1,0,0), runner.shadow_location().loc);
         let program = FromActionsProgram::from(
             &[go::forward(), go::forward()]
         );
-        runner.run(Box::new(program));
+        assert!(runner.run(Box::new(program)).is_finished());
         assert_eq!(Coord::new(2,0,0), runner.location().loc);
         assert_eq!(Coord::new(2,0,0), runner.shadow_location().loc);
     }
@@ -264,7 +1222,7 @@ mod tests {
             &[turn::right(), go::forward(), go::forward()]
         );
         let mut runner = Runner::make_world_unknown_loc_known_originxp("test_box");
-        runner.run(Box::new(program));
+        assert!(runner.run(Box::new(program)).is_finished());
         assert_eq!(Coord::new(0,0,2), runner.shadow_location().loc);
         assert_eq!(Block::Unknown, runner.world().get(&Coord::new(0,0,3)));
         let response = runner.execute_action(&detect::forward());
@@ -273,6 +1231,25 @@ mod tests {
 
     }
 
+    #[test]
+    fn runner_reports_unlimited_fuel() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        assert_eq!(TurtleActionReturn::Unlimited, runner.execute_action(&fuel::level()));
+        runner.execute_action(&go::forward());
+        assert_eq!(Some(true), runner.turtle.state.fuel.has_fuel_for(100));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoItemsToCombust), runner.execute_action(&fuel::refuel()));
+    }
+
+    #[test]
+    fn runner_with_empty_inventory() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.execute_action(&inventory::selected());
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoItemsToDrop), runner.execute_action(&drop::forward_count(2)));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoItemsToTake), runner.execute_action(&suck::up_count(2)));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&inventory::transfer_to_count(3, 1)));
+        assert!(runner.turtle.state.inventory.selected_slot().unwrap().is_empty());
+    }
+
     #[test]
     fn runner_has_sense_of_direction() {
         let mut runner = Runner::make_world_unknown_loc_unknown("test_box", Coord::zero(), AxisDirection::Zm);
@@ -286,4 +1263,529 @@ mod tests {
 
     }
 
+
+    #[test]
+    fn runner_digs_blocks() {
+        let mut runner = Runner::make_world_unknown_loc_known_originxp("dig_box");
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&dig::forward()));
+        assert_eq!(Block::Air, runner.shadow_world().get(&Coord::new(1,0,0)));
+        assert_eq!(Block::Air, runner.world().get(&Coord::new(1,0,0)));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NothingToDigHere), runner.execute_action(&dig::forward()));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::UnbreakableBlockDetected), runner.execute_action(&dig::down()));
+        assert_eq!(Block::Bedrock, runner.world().get(&Coord::new(0,-1,0)));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&dig::up()));
+        assert_eq!(TurtleActionReturn::Boolean(true), runner.execute_action(&detect::down()));
+        assert_eq!(Block::Bedrock, runner.world().get(&Coord::new(0,-1,0)));
+
+        let dug = &runner.shadow_state.inventory;
+        assert_eq!(&Slot::item("minecraft:cobblestone", 2, Confidence::Observed), dug.slot(1));
+        assert!(dug.slot(2).is_empty());
+    }
+
+    #[test]
+    fn runner_places_blocks() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        let front = Coord::new(1,0,0);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoItemsToPlace), runner.execute_action(&place::forward()));
+        runner.add_items("minecraft:cobblestone", 2);
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&place::forward()));
+        assert_eq!(Block::Block, runner.shadow_world().get(&front));
+        assert_eq!(Block::Block, runner.world().get(&front));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::CanNotPlaceBlockHere), runner.execute_action(&place::forward()));
+        assert_eq!(1, runner.shadow_inventory().count_of("minecraft:cobblestone"));
+    }
+
+    #[test]
+    fn runner_drops_and_sucks_item_entities() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        let front = Coord::new(1,0,0);
+        runner.add_items("minecraft:coal", 10);
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&drop::forward_count(4)));
+        assert_eq!(&[("minecraft:coal".to_string(), 4)], runner.items_at(&front));
+        assert_eq!(6, runner.shadow_inventory().count_of("minecraft:coal"));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&suck::forward_count(3)));
+        assert_eq!(&[("minecraft:coal".to_string(), 1)], runner.items_at(&front));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&suck::forward()));
+        assert!(runner.items_at(&front).is_empty());
+        assert_eq!(10, runner.shadow_inventory().count_of("minecraft:coal"));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoItemsToTake), runner.execute_action(&suck::forward()));
+    }
+
+    #[test]
+    fn runner_uses_chests() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        let front = Coord::new(1,0,0);
+        runner.add_chest(front.clone(), &[("minecraft:iron_ingot", 70)]);
+        assert_eq!(TurtleActionReturn::Boolean(true), runner.execute_action(&detect::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&suck::forward()));
+        assert_eq!(64, runner.shadow_inventory().count_of("minecraft:iron_ingot"));
+        assert_eq!(6, runner.container(&front).unwrap().count_of("minecraft:iron_ingot"));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&drop::forward_count(10)));
+        assert_eq!(16, runner.container(&front).unwrap().count_of("minecraft:iron_ingot"));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&drop::forward()));
+        assert_eq!(70, runner.container(&front).unwrap().count_of("minecraft:iron_ingot"));
+
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&dig::forward()));
+        assert!(runner.container(&front).is_none());
+        assert_eq!(1, runner.shadow_inventory().count_of(Runner::CHEST_ITEM));
+        assert_eq!(&[("minecraft:iron_ingot".to_string(), 6), ("minecraft:iron_ingot".to_string(), 64)], runner.items_at(&front));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&place::forward()));
+        assert_eq!(Some(&Container::new(CHEST_SIZE)), runner.container(&front));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoItemsToDrop), runner.execute_action(&drop::forward()));
+    }
+
+    #[test]
+    fn runner_reports_full_chest() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        let front = Coord::new(1,0,0);
+        runner.add_chest(front.clone(), &[("minecraft:dirt", 64 * CHEST_SIZE as u32)]);
+        runner.add_items("minecraft:sand", 1);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoSpaceForItems), runner.execute_action(&drop::forward()));
+        assert_eq!(1, runner.shadow_inventory().count_of("minecraft:sand"));
+    }
+
+    #[test]
+    fn runner_inspects_named_blocks() {
+        let mut runner = Runner::make_world_unknown_loc_known_originxp("ore_box");
+        assert_eq!(TurtleActionReturn::InspectSuccess("minecraft:iron_ore".to_string(), Map::new()), runner.execute_action(&inspect::forward()));
+        assert_eq!(Block::Block, runner.world().get(&Coord::new(1,0,0)));
+        assert_eq!(vec![Coord::new(1,0,0)], runner.world().positions_of("minecraft:iron_ore"));
+        let mut state = Map::new();
+        state.insert("axis".to_string(), Value::from("y"));
+        assert_eq!(TurtleActionReturn::InspectSuccess("minecraft:oak_log".to_string(), state), runner.execute_action(&inspect::up()));
+        assert_eq!(TurtleActionReturn::InspectSuccess(Runner::BEDROCK.to_string(), Map::new()), runner.execute_action(&inspect::down()));
+        runner.execute_action(&turn::left());
+        assert_eq!(TurtleActionReturn::InspectSuccess(Runner::DEFAULT_BLOCK.to_string(), Map::new()), runner.execute_action(&inspect::forward()));
+
+        runner.execute_action(&turn::right());
+        runner.execute_action(&dig::forward());
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoBlockToInspect), runner.execute_action(&inspect::forward()));
+        assert!(runner.world().positions_of("minecraft:iron_ore").is_empty());
+        assert_eq!(Some("y"), runner.world().identity(&Coord::new(0,1,0)).unwrap().state["axis"].as_str());
+        runner.execute_action(&turn::left());
+        runner.execute_action(&turn::left());
+        runner.execute_action(&dig::forward());
+        assert_eq!(1, runner.shadow_inventory().count_of("minecraft:iron_ore"));
+        assert_eq!(1, runner.shadow_inventory().count_of("minecraft:diamond"));
+    }
+
+    #[test]
+    fn runner_compares_blocks_and_slots() {
+        let mut runner = Runner::make_world_unknown_loc_known_originxp("ore_box");
+        assert_eq!(TurtleActionReturn::Boolean(false), runner.execute_action(&compare::forward()));
+        assert_eq!(TurtleActionReturn::Boolean(true), runner.execute_action(&inventory::compare_to(2)));
+        runner.add_items("minecraft:iron_ore", 1);
+        assert_eq!(TurtleActionReturn::Boolean(true), runner.execute_action(&compare::forward()));
+        assert_eq!(TurtleActionReturn::Boolean(false), runner.execute_action(&compare::up()));
+        assert_eq!(TurtleActionReturn::Boolean(false), runner.execute_action(&inventory::compare_to(2)));
+        runner.execute_action(&dig::forward());
+        assert_eq!(TurtleActionReturn::Boolean(false), runner.execute_action(&compare::forward()));
+        runner.execute_action(&place::forward());
+        assert_eq!(TurtleActionReturn::InspectSuccess("minecraft:iron_ore".to_string(), Map::new()), runner.execute_action(&inspect::forward()));
+        assert_eq!(TurtleActionReturn::Boolean(true), runner.execute_action(&compare::forward()));
+    }
+
+    #[test]
+    fn runner_answers_slot_queries() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.set_slot(3, Slot {damage: Some(5), ..Slot::item("minecraft:diamond_pickaxe", 1, Confidence::Unknown)});
+        runner.add_items("minecraft:ender_pearl", 20);
+        assert_eq!(TurtleActionReturn::Number(16), runner.execute_action(&inventory::count(1)));
+        assert_eq!(TurtleActionReturn::Number(0), runner.execute_action(&inventory::space(1)));
+        assert_eq!(TurtleActionReturn::Number(4), runner.execute_action(&inventory::count(2)));
+        assert_eq!(TurtleActionReturn::Number(12), runner.execute_action(&inventory::space(2)));
+        assert_eq!(TurtleActionReturn::Number(64), runner.execute_action(&inventory::space(4)));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::SlotIsEmpty), runner.execute_action(&inventory::detail(4)));
+        let detail = json!({"name": "minecraft:diamond_pickaxe", "count": 1, "damage": 5});
+        assert_eq!(TurtleActionReturn::DetailSuccess(detail.as_object().unwrap().to_owned()), runner.execute_action(&inventory::detail(3)));
+        let detail = json!({"name": "minecraft:ender_pearl", "count": 4, "damage": 0, "maxCount": 16});
+        assert_eq!(TurtleActionReturn::DetailSuccess(detail.as_object().unwrap().to_owned()), runner.execute_action(&inventory::detail_full(2)));
+        // The turtle learns what it queried
+        assert_eq!(Some(4), runner.turtle.state.inventory.slot(2).count);
+        assert_eq!(Some(16), runner.turtle.state.inventory.slot(2).max_count);
+    }
+
+    #[test]
+    fn runner_moves_items_between_slots() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.add_items("minecraft:egg", 10);
+        runner.set_slot(2, Slot::item("minecraft:egg", 12, Confidence::Observed));
+        runner.set_slot(3, Slot::item("minecraft:dirt", 1, Confidence::Observed));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&inventory::transfer_to(2)));
+        assert_eq!(TurtleActionReturn::Number(6), runner.execute_action(&inventory::count(1)));
+        assert_eq!(TurtleActionReturn::Number(16), runner.execute_action(&inventory::count(2)));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoSpaceForItems), runner.execute_action(&inventory::transfer_to(2)));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoSpaceForItems), runner.execute_action(&inventory::transfer_to(3)));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&inventory::transfer_to_count(4, 2)));
+        assert_eq!(TurtleActionReturn::Number(2), runner.execute_action(&inventory::count(4)));
+        assert_eq!(TurtleActionReturn::Boolean(true), runner.execute_action(&inventory::compare_to(4)));
+        assert_eq!(TurtleActionReturn::Boolean(true), runner.execute_action(&inventory::select(3)));
+        assert_eq!(TurtleActionReturn::Number(3), runner.execute_action(&inventory::selected()));
+        assert_eq!(TurtleActionReturn::Boolean(false), runner.execute_action(&inventory::compare_to(4)));
+    }
+
+    #[test]
+    fn runner_equips_upgrades() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.add_items("minecraft:dirt", 1);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NotAValidUpgrade), runner.execute_action(&equip::left()));
+        runner.set_slot(1, Slot::item("minecraft:diamond_pickaxe", 1, Confidence::Observed));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&equip::left()));
+        assert_eq!(Some("minecraft:diamond_pickaxe".to_string()), runner.shadow_inventory().left.name);
+        assert!(runner.shadow_inventory().slot(1).is_empty());
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&equip::left()));
+        assert!(runner.shadow_inventory().left.is_empty());
+        assert_eq!(1, runner.shadow_inventory().count_of("minecraft:diamond_pickaxe"));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn runner_rejects_invalid_slots() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.execute_action(&inventory::select(17));
+    }
+
+    #[test]
+    fn runner_consumes_fuel() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.set_fuel(2, 1000);
+        assert_eq!(TurtleActionReturn::Number(2), runner.execute_action(&fuel::level()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&turn::left()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(FuelLevel::Known(0), runner.shadow_fuel().level);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::OutOfFuel), runner.execute_action(&go::forward()));
+        assert_eq!(Coord::new(1,0,-1), runner.location().loc);
+        assert_eq!(Some(false), runner.turtle.state.fuel.has_fuel_for(1));
+        // Running out of fuel says nothing about the way ahead
+        let ahead = Coord::new(1,0,-2);
+        assert_eq!(Block::Air, runner.shadow_world().get(&ahead));
+        assert_ne!(Block::Block, runner.turtle.state.world.get(&ahead));
+
+        runner.add_items("minecraft:coal", 1);
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&fuel::refuel()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(Coord::new(1,0,-2), runner.location().loc);
+    }
+
+    #[test]
+    fn runner_refuels_from_inventory() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.set_fuel(0, 1000);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NoItemsToCombust), runner.execute_action(&fuel::refuel()));
+        runner.add_items("minecraft:dirt", 1);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::ItemsNotCombustible), runner.execute_action(&fuel::refuel()));
+        runner.set_slot(1, Slot::item("minecraft:coal", 20, Confidence::Observed));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&fuel::refuel_count(0)));
+        assert_eq!(TurtleActionReturn::Number(0), runner.execute_action(&fuel::level()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&fuel::refuel_count(2)));
+        assert_eq!(TurtleActionReturn::Number(160), runner.execute_action(&fuel::level()));
+        assert_eq!(TurtleActionReturn::Number(18), runner.execute_action(&inventory::count(1)));
+        // The level stops at the limit, but all the items are burned
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&fuel::refuel()));
+        assert_eq!(TurtleActionReturn::Number(1000), runner.execute_action(&fuel::level()));
+        assert_eq!(TurtleActionReturn::Number(1000), runner.execute_action(&fuel::limit()));
+        assert!(runner.shadow_inventory().slot(1).is_empty());
+
+        runner.set_fuel(0, 20000);
+        runner.set_slot(1, Slot::item("minecraft:lava_bucket", 1, Confidence::Observed));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&fuel::refuel()));
+        assert_eq!(FuelLevel::Known(1000), runner.shadow_fuel().level);
+        assert_eq!(Some("minecraft:bucket".to_string()), runner.shadow_inventory().slot(1).name);
+    }
+
+    #[test]
+    fn runner_drops_gravity_blocks_into_dug_space() {
+        let mut runner = Runner::make_world_unknown_loc_known_originxp("sand_box");
+        let front = Coord::new(1,0,0);
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&dig::forward()));
+        assert_eq!(Some(BlockIdentity::new("minecraft:sand")), runner.shadow_block(&front));
+        assert_eq!(Some(BlockIdentity::new("minecraft:gravel")), runner.shadow_block(&Coord::new(1,1,0)));
+        assert_eq!(None, runner.shadow_block(&Coord::new(1,2,0)));
+        // The turtle thinks it cleared the way, but sand fell in front of it
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::MovementObstructed), runner.execute_action(&go::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&dig::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&dig::forward()));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NothingToDigHere), runner.execute_action(&dig::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(1, runner.shadow_inventory().count_of("minecraft:sand"));
+        assert_eq!(1, runner.shadow_inventory().count_of("minecraft:gravel"));
+    }
+
+    #[test]
+    fn runner_drops_gravity_blocks_behind_turtle() {
+        let mut runner = Runner::make_world_unknown_loc_known_originxp("sand_box");
+        // Sand can't fall into the space the turtle is in
+        assert_eq!(Some(BlockIdentity::new("minecraft:sand")), runner.shadow_block(&Coord::new(0,1,0)));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::backward()));
+        assert_eq!(Some(BlockIdentity::new("minecraft:sand")), runner.shadow_block(&Coord::zero()));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::MovementObstructed), runner.execute_action(&go::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&dig::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(Some(BlockIdentity::new("minecraft:sand")), runner.shadow_block(&Coord::new(-1,0,0)));
+        assert_eq!(None, runner.shadow_block(&Coord::new(-1,1,0)));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::MovementObstructed), runner.execute_action(&go::backward()));
+    }
+
+    #[test]
+    fn runner_reports_run_outcomes() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        assert_eq!(RunOutcome::Finished {steps: 2}, runner.run(Box::new(FromActionsProgram::from(&[turn::right(); 2]))));
+
+        let spin = || Box::new(FromActionsProgram::new(vec![turn::right(); 1000]));
+        let limits = RunLimits {max_steps: Some(10), stuck_after: None, ..RunLimits::default()};
+        assert_eq!(RunOutcome::BudgetExceeded {steps: 10, fuel: 0}, runner.run_with_limits(spin(), &limits));
+        match runner.run_with_limits(spin(), &RunLimits {stuck_after: Some(3), ..RunLimits::default()}) {
+            RunOutcome::Stuck {steps, location, ..} => {
+                assert!(steps <= 4 * 4);
+                assert_eq!(Coord::zero(), location);
+            },
+            x => panic!("Expected to be stuck, got {:?}", x)
+        }
+
+        let back_and_forth: Vec<TurtleAction> = [go::forward(), go::backward()].iter().cycle().take(20).copied().collect();
+        let limits = RunLimits {max_fuel: Some(3), ..RunLimits::default()};
+        assert_eq!(RunOutcome::BudgetExceeded {steps: 3, fuel: 3}, runner.run_with_limits(Box::new(FromActionsProgram::new(back_and_forth)), &limits));
+    }
+
+    #[test]
+    fn runner_catches_panics() {
+        /// Fails on the result of its second action
+        struct PanickingProgram {
+            updates: u32
+        }
+        impl TurtleProgram for PanickingProgram {
+            fn next(&mut self) -> anyhow::Result<TurtleAction> {
+                Ok(turn::left())
+            }
+            fn progress(&self) -> (u32, u32) {
+                (self.updates, 3)
+            }
+            fn name(&self) -> &str {
+                "panicking"
+            }
+            fn update(&mut self, _state: &TurtleState, _action: &TurtleAction, _result: &TurtleActionReturn) {
+                self.updates += 1;
+                assert!(self.updates < 2, "Program failed");
+            }
+        }
+
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        match runner.run(Box::new(PanickingProgram {updates: 0})) {
+            RunOutcome::Panicked {steps, message} => {
+                assert_eq!(1, steps);
+                assert_eq!("Program failed", message);
+            },
+            x => panic!("Expected a panic, got {:?}", x)
+        }
+
+        // A bug of the simulator isn't the program's, the single layer of test_box has nothing above it
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        let run = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            runner.run(Box::new(FromActionsProgram::from(&[turn::left(), go::up()])))
+        }));
+        assert!(run.is_err());
+    }
+
+    #[test]
+    fn runner_reports_program_errors() {
+        /// Has no action for its second step
+        struct FailingProgram {
+            updates: u32
+        }
+        impl TurtleProgram for FailingProgram {
+            fn next(&mut self) -> anyhow::Result<TurtleAction> {
+                match self.updates {
+                    0 => Ok(turn::left()),
+                    _ => Err(anyhow!("Program failed"))
+                }
+            }
+            fn progress(&self) -> (u32, u32) {
+                (self.updates, 3)
+            }
+            fn name(&self) -> &str {
+                "failing"
+            }
+            fn update(&mut self, _state: &TurtleState, _action: &TurtleAction, _result: &TurtleActionReturn) {
+                self.updates += 1;
+            }
+        }
+
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        assert_eq!(RunOutcome::Failed {steps: 1, message: "Program failed".to_string()},
+            runner.run(Box::new(FailingProgram {updates: 0})));
+    }
+
+    #[test]
+    fn runner_moves_through_fluids() {
+        let mut runner = Runner::make_world_unknown_loc_known_originxp("lava_box");
+        let lava = Coord::new(1, 0, 0);
+        let water = Coord::new(-1, 0, 0);
+        assert_eq!(TurtleActionReturn::Boolean(false), runner.execute_action(&detect::forward()));
+        assert_eq!(TurtleActionReturn::InspectSuccess(Runner::LAVA.to_string(), json!({"level": 0}).as_object().unwrap().clone()),
+            runner.execute_action(&inspect::forward()));
+        assert_eq!(Block::Lava, runner.world().get(&lava));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NothingToDigHere), runner.execute_action(&dig::forward()));
+        assert_eq!(Block::Lava, runner.world().get(&lava));
+
+        // Lava burns the dropped items
+        runner.add_items("minecraft:cobblestone", 3);
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&drop::forward_count(1)));
+        assert!(runner.items_at(&lava).is_empty());
+        assert_eq!(2, runner.shadow_inventory().count_of("minecraft:cobblestone"));
+
+        // The turtle displaces a lava source, but flowing water comes back
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::backward()));
+        assert_eq!(None, runner.shadow_block(&lava));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::backward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(Some(BlockIdentity::fluid(Runner::WATER, 2)), runner.shadow_block(&water));
+    }
+
+    #[test]
+    fn runner_attacks_entities() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        let dest = Coord::new(1, 0, 0);
+        runner.add_entity(dest.clone(), Entity::new("minecraft:zombie", 20, &[("minecraft:rotten_flesh", 2)]));
+        runner.add_items("minecraft:dirt", 1);
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::MovementObstructed), runner.execute_action(&go::forward()));
+        assert_eq!(Block::Air, runner.shadow_world().get(&dest));
+        assert_eq!(Block::Unknown, runner.world().get(&dest));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::MovementObstructed), runner.execute_action(&go::forward()));
+        assert_eq!(Block::Block, runner.world().get(&dest));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::CanNotPlaceBlockHere), runner.execute_action(&place::forward()));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NothingToAttackHere), runner.execute_action(&attack::up()));
+
+        // Attack until the zombie dies, then retry the move
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&attack::forward()));
+        // A hit tells the turtle that it's not a block in the way
+        assert_eq!(Block::Unknown, runner.world().get(&dest));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&attack::forward()));
+        assert_eq!(4, runner.entity_at(&dest).unwrap().health);
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&attack::forward()));
+        assert_eq!(None, runner.entity_at(&dest));
+        assert_eq!(2, runner.shadow_inventory().count_of("minecraft:rotten_flesh"));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::NothingToAttackHere), runner.execute_action(&attack::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(Some(dest), runner.location().loc_absolute);
+    }
+
+    #[test]
+    fn runner_loads_scenario() {
+        let mut runner = Runner::from_scenario("mine_scenario").unwrap();
+        assert_eq!(None, runner.location().loc_absolute);
+        assert!(runner.world().state.is_empty());
+        assert_eq!(16, runner.container(&Coord::new(-1, 0, 0)).unwrap().count_of("minecraft:torch"));
+        assert_eq!(Block::Bedrock, runner.shadow_world().get(&Coord::new(0, -1, 0)));
+        assert!(runner.check_expectations().unwrap_err().to_string().contains("position"));
+
+        // Gps only works in front of the turtle
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::GpsLocateFailure), runner.execute_action(&gps::locate()));
+        assert_eq!(TurtleActionReturn::Failure(FailureReason::OutOfFuel), runner.execute_action(&go::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&fuel::refuel_count(1)));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&dig::forward()));
+        assert_eq!(TurtleActionReturn::Success, runner.execute_action(&go::forward()));
+        assert_eq!(TurtleActionReturn::Coordinate(Coord::new(1, 0, 0)), runner.execute_action(&gps::locate()));
+        runner.check_expectations().unwrap();
+    }
+
+    #[test]
+    fn runner_injects_world_faults() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.set_faults(FaultConfig {gps_timeout: 1.0, mob_obstruction: 1.0, mob_steps: 2, ..FaultConfig::default()}, 0);
+        assert_eq!(Ok(TurtleActionReturn::Failure(FailureReason::GpsLocateFailure)), runner.try_execute_action(&gps::locate()));
+        let dest = Coord::new(1, 0, 0);
+        assert_eq!(Ok(TurtleActionReturn::Failure(FailureReason::MovementObstructed)), runner.try_execute_action(&go::forward()));
+        assert_eq!(Block::Unknown, runner.world().get(&dest));
+        assert_eq!(Block::Air, runner.shadow_world().get(&dest));
+
+        // The mob moves on after a while
+        runner.faults_mut().unwrap().config.mob_obstruction = 0.0;
+        assert_eq!(Ok(TurtleActionReturn::Failure(FailureReason::MovementObstructed)), runner.try_execute_action(&go::forward()));
+        assert_eq!(Ok(TurtleActionReturn::Success), runner.try_execute_action(&go::forward()));
+
+        // or can be killed
+        runner.faults_mut().unwrap().config.mob_obstruction = 1.0;
+        assert_eq!(Ok(TurtleActionReturn::Failure(FailureReason::MovementObstructed)), runner.try_execute_action(&go::forward()));
+        runner.faults_mut().unwrap().config.mob_obstruction = 0.0;
+        assert_eq!(Ok(TurtleActionReturn::Success), runner.try_execute_action(&attack::forward()));
+        assert_eq!(Ok(TurtleActionReturn::Failure(FailureReason::NothingToAttackHere)), runner.try_execute_action(&attack::forward()));
+        assert_eq!(Ok(TurtleActionReturn::Success), runner.try_execute_action(&go::forward()));
+        let dest = Coord::new(2, 0, 0);
+        assert_eq!(Some(dest), runner.location().loc_absolute);
+        let faults: Vec<Fault> = runner.faults().unwrap().injected().iter().map(|(_, fault)| fault.clone()).collect();
+        assert_eq!(vec![Fault::GpsTimeout, Fault::MobObstruction, Fault::MobObstruction, Fault::MobObstruction], faults);
+    }
+
+    #[test]
+    fn runner_injects_connection_faults() {
+        let mut runner = Runner::make_world_known_loc_known_originxp("test_box");
+        runner.set_faults(FaultConfig {dropped_response: 1.0, ..FaultConfig::default()}, 0);
+        assert_eq!(Err(Fault::DroppedResponse), runner.try_execute_action(&go::forward()));
+        assert_eq!(Some(Coord::new(1, 0, 0)), runner.shadow_location().loc_absolute);
+        assert_eq!(Some(Coord::zero()), runner.location().loc_absolute);
+
+        runner.set_faults(FaultConfig {disconnect: 1.0, ..FaultConfig::default()}, 0);
+        assert_eq!(Err(Fault::Disconnect), runner.try_execute_action(&go::forward()));
+        assert_eq!(Some(Coord::new(1, 0, 0)), runner.shadow_location().loc_absolute);
+        assert_eq!(None, runner.location().loc_absolute);
+        assert_eq!(49, runner.world().state.len());
+    }
+
+    #[test]
+    fn replay_of_simulated_run_matches() {
+        let program = FromActionsProgram::from(
+            &[turn::right(), go::forward(), go::forward(), detect::forward()]
+        );
+        let runs_dir = format!("{}/replay_test", std::env::temp_dir().display());
+        let _ = std::fs::remove_dir_all(&runs_dir);
+        let mut runner = Runner::make_world_unknown_loc_known_originxp("test_box");
+        runner.turtle.record_to(&runs_dir).unwrap();
+        assert!(runner.run(Box::new(program)).is_finished());
+        let path = runner.turtle.run_history.as_ref().unwrap().path().to_string();
+        let mut replayer = Replayer::from_file(&path).unwrap();
+        replayer.run().unwrap();
+        assert!(replayer.turtle.run_history.is_none(), "Replays are not recorded again");
+        assert_eq!(runner.location().loc, replayer.turtle.state.location.loc);
+        assert_eq!(Block::Block, replayer.turtle.state.world.get(&Coord::new(0,0,3)));
+
+        let mut events = RunHistory::read(&path).unwrap();
+        if let RunEvent::Action {world, ..} = &mut events[4] {
+            world.clear();
+        }
+        let divergence = Replayer::new(events).unwrap().run().unwrap_err();
+        assert_eq!(4, divergence.step);
+        assert!(matches!(divergence.kind, DivergenceKind::World {..}));
+        std::fs::remove_dir_all(&runs_dir).unwrap();
+    }
+
+    #[test]
+    fn replay_reports_gps_mismatch() {
+        let mut location = LocationState::new();
+        let ore = (Coord::new(0,-1,0), BlockIdentity::new("minecraft:diamond_ore"));
+        let mut events = vec![RunEvent::Initial {location: location.clone(), world: vec![], names: vec![ore], inventory: InventoryState::new()}];
+        let steps = [
+            (gps::locate(), TurtleActionReturn::Coordinate(Coord::new(5,0,5))),
+            (go::forward(), TurtleActionReturn::Success),
+            (gps::locate(), TurtleActionReturn::Coordinate(Coord::new(6,0,5))),
+        ];
+        for (action, result) in steps.iter() {
+            location.update(action, result).unwrap();
+            events.push(RunEvent::Action {action: *action, result: result.clone(), location: location.clone(), world: vec![]});
+        }
+        // The recorded result disagrees with what the earlier measurements imply
+        events.push(RunEvent::Action {
+            action: gps::locate(),
+            result: TurtleActionReturn::Coordinate(Coord::new(9,0,9)),
+            location: location.clone(),
+            world: vec![]
+        });
+        let mut replayer = Replayer::new(events).unwrap();
+        assert_eq!(Some("minecraft:diamond_ore"), replayer.turtle.state.world.name_at(&Coord::new(0,-1,0)));
+        let divergence = replayer.run().unwrap_err();
+        assert_eq!(4, divergence.step);
+        assert!(matches!(divergence.kind, DivergenceKind::UpdateFailed(_)));
+    }
+
 }
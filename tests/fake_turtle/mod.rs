@@ -0,0 +1,111 @@
+use std::net::TcpStream;
+use anyhow::{anyhow, Result};
+use tungstenite::{client, HandshakeError, Message, WebSocket};
+use turtlers::protocol::*;
+use turtlers::turtle_action::TurtleAction;
+use turtlers::turtle_program::StartProgramMsg;
+use turtlers::world_simulator::Runner;
+
+/// A turtle client for testing the server without Minecraft. It talks to the server over a real
+/// websocket, and answers the calls from the shadow world of a `Runner`.
+pub struct FakeTurtle {
+    pub runner: Runner,
+    socket: WebSocket<TcpStream>,
+    calls: Vec<TurtleApiCall> // every call received, including stops
+}
+
+impl FakeTurtle {
+    /// Connects to the server at `addr`, e.g. "127.0.0.1:8080", and does the handshake
+    pub fn connect(addr: &str, id: &str, runner: Runner) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let (socket, _) = client(format!("ws://{}", addr), stream).map_err(|e| match e {
+            HandshakeError::Interrupted(_) => anyhow!("Bug: blocking socket would block"),
+            HandshakeError::Failure(f) => f.into()
+        })?;
+        let mut turtle = FakeTurtle {runner, socket, calls: vec![]};
+        turtle.send(&serde_json::to_string(&InitMsg::new(id))?)?;
+        match turtle.receive()? {
+            ServerMsg::Handshake(HandshakeReply::Ok {..}) => Ok(turtle),
+            ServerMsg::Handshake(HandshakeReply::Error {error, ..}) => Err(anyhow!("Handshake refused: {}", error)),
+            ServerMsg::Call(call) => Err(anyhow!("Expected a handshake reply, got {:?}", call))
+        }
+    }
+
+    /// Starts a program on the server, e.g. `["rotate", "2"]`. Returns the first call.
+    pub fn start(&mut self, args: &[&str]) -> Result<TurtleApiCall> {
+        let msg = ClientMsg::Start(StartProgramMsg {args: args.iter().map(|arg| arg.to_string()).collect()});
+        self.send(&serde_json::to_string(&msg)?)?;
+        self.next_call()
+    }
+
+    /// Executes the call in the shadow world and returns the next one
+    pub fn execute(&mut self, call: &TurtleApiCall) -> Result<TurtleApiCall> {
+        let action = parse_call(call)?;
+        if action == TurtleAction::Stop {
+            return Err(anyhow!("The turtle has stopped"));
+        }
+        let response = self.runner.answer(&action);
+        let msg = ClientMsg::Response {result: pack_response(&response)};
+        self.send(&serde_json::to_string(&msg)?)?;
+        self.next_call()
+    }
+
+    /// Starts the program and executes its calls until the server says stop. Returns the number
+    /// of actions executed.
+    pub fn run(&mut self, args: &[&str]) -> Result<usize> {
+        let mut call = self.start(args)?;
+        let mut count = 0;
+        while parse_call(&call)? != TurtleAction::Stop {
+            call = self.execute(&call)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Sends a message as is, for testing how the server handles messages it doesn't expect
+    pub fn send_raw(&mut self, msg: &str) -> Result<TurtleApiCall> {
+        self.send(msg)?;
+        self.next_call()
+    }
+
+    pub fn calls(&self) -> &[TurtleApiCall] {
+        &self.calls
+    }
+
+    /// Closes the connection and waits for the server to acknowledge it
+    pub fn close(mut self) -> Result<()> {
+        self.socket.close(None)?;
+        loop {
+            match self.socket.read_message() {
+                Ok(_) => {},
+                Err(tungstenite::Error::ConnectionClosed) | Err(tungstenite::Error::AlreadyClosed) => return Ok(()),
+                Err(e) => return Err(e.into())
+            }
+        }
+    }
+
+    fn send(&mut self, msg: &str) -> Result<()> {
+        self.socket.write_message(Message::Text(msg.to_string()))?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<ServerMsg> {
+        loop {
+            match self.socket.read_message()? {
+                Message::Text(x) => return Ok(serde_json::from_str(&x)?),
+                Message::Ping(_) | Message::Pong(_) => {},
+                x => return Err(anyhow!("Unexpected message {:?}", x))
+            }
+        }
+    }
+
+    fn next_call(&mut self) -> Result<TurtleApiCall> {
+        match self.receive()? {
+            ServerMsg::Call(call) => {
+                self.calls.push(call.clone());
+                Ok(call)
+            },
+            ServerMsg::Handshake(reply) => Err(anyhow!("Expected a call, got {:?}", reply))
+        }
+    }
+}
@@ -1,4 +1,5 @@
 
+use turtlers::faults::{Fault, FaultConfig};
 use turtlers::world_simulator::Runner;
 use turtlers::turtle_program::{InitGpsProgram, MultiProgram};
 use turtlers::turtle_state::Coord;
@@ -22,7 +23,7 @@ mod tests {
     fn multiprogram_finds_gps() {
         let multi = MultiProgram::new(Box::new(InitGpsProgram::new()));
         let mut runner = Runner::make_world_unknown_loc_unknown_originxp("test_box");
-        runner.run(Box::new(multi));
+        assert!(runner.run(Box::new(multi)).is_finished());
         assert_eq!(LocationMode::Absolute((Coord::zero(), Rotation::Y0)), runner.location().location_precision)
     }
 
@@ -31,7 +32,7 @@ mod tests {
         let coord = Coord::new(0,0,0);
         let program = create_gps_and_pathfinder(&coord, &AxisDirection::Xp);
         let mut runner = Runner::make_world_unknown_loc_unknown_originxp("test_box");
-        runner.run(program);
+        assert!(runner.run(program).is_finished());
         assert_eq!(2, runner.history().move_steps_len());
 
     }
@@ -41,7 +42,7 @@ mod tests {
         let coord = Coord::new(2,0,2);
         let program = create_gps_and_pathfinder(&coord, &AxisDirection::Xp);
         let mut runner = Runner::make_world_unknown_loc_unknown_originxp("test_box");
-        runner.run(program);
+        assert!(runner.run(program).is_finished());
         assert_eq!(Some(coord), runner.location().loc_absolute);
         assert_eq!(8, runner.history().move_steps_len());
         runner.location().print_history();
@@ -52,7 +53,7 @@ mod tests {
         let coord = Coord::new(2,0,2);
         let program = create_gps_and_pathfinder(&coord, &AxisDirection::Xp);
         let mut runner = Runner::make_world_unknown_loc_unknown("test_box", Coord::new(0,0,0), AxisDirection::Zm);
-        runner.run(program);
+        assert!(runner.run(program).is_finished());
 
         runner.location().print_history();
         assert_eq!(Some(coord), runner.location().loc_absolute);
@@ -64,7 +65,7 @@ mod tests {
         let coord = Coord::new(-2,0,-2);
         let program = create_gps_and_pathfinder(&coord, &AxisDirection::Xp);
         let mut runner = Runner::make_world_unknown_loc_unknown("test_box", Coord::new(0,0,0), AxisDirection::Zp);
-        runner.run(program);
+        assert!(runner.run(program).is_finished());
 
         runner.location().print_history();
         assert_eq!(Some(coord), runner.location().loc_absolute);
@@ -77,7 +78,7 @@ mod tests {
         let coord = Coord::new(2,0,0);
         let program = create_gps_and_pathfinder(&coord, &AxisDirection::Xp);
         let mut runner = Runner::make_world_unknown_loc_unknown("box_w_wall", Coord::new(0,0,0), AxisDirection::Xp);
-        runner.run(program);
+        assert!(runner.run(program).is_finished());
         runner.location().print_history();
         assert_eq!(Some(coord), runner.location().loc_absolute);
         assert_eq!(AxisDirection::Xp, runner.location().direction_absolute);
@@ -85,5 +86,27 @@ mod tests {
         // todo: this really should be much faster :D 
     }
 
+    #[test]
+    fn pathfinder_avoids_lava() {
+        let coord = Coord::new(2,0,0);
+        let program = create_gps_and_pathfinder(&coord, &AxisDirection::Xp);
+        let mut runner = Runner::make_world_known_loc_known_originxp("lava_box");
+        assert!(runner.run(program).is_finished());
+        runner.location().print_history();
+        assert_eq!(Some(coord), runner.location().loc_absolute);
+        assert!(runner.location().history.iter().all(|(loc, _)| loc != &Coord::new(1,0,0)));
+    }
+
 
-}
\ No newline at end of file
+
+    #[test]
+    fn pathfinder_gets_past_mobs() {
+        let coord = Coord::new(2,0,2);
+        let program = create_gps_and_pathfinder(&coord, &AxisDirection::Xp);
+        let mut runner = Runner::make_world_unknown_loc_unknown_originxp("box_w_wall");
+        runner.set_faults(FaultConfig {mob_obstruction: 0.2, ..FaultConfig::default()}, 4);
+        assert!(runner.run(program).is_finished());
+        assert_eq!(Some(coord), runner.location().loc_absolute);
+        assert!(runner.faults().unwrap().injected().iter().any(|(_, fault)| fault == &Fault::MobObstruction));
+    }
+}
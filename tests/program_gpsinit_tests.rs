@@ -1,5 +1,7 @@
 
-use turtlers::world_simulator::Runner;
+use turtlers::faults::{Fault, FaultConfig};
+use turtlers::scenario::Region;
+use turtlers::world_simulator::{Runner, RunOutcome};
 use turtlers::turtle_program::InitGpsProgram;
 use turtlers::turtle_state::Coord;
 
@@ -14,7 +16,7 @@ mod tests {
         let program = InitGpsProgram::new();
         assert_eq!(0, runner.world().state.len());
         assert_eq!(Option::None, runner.location().loc_absolute);
-        runner.run(Box::new(program));
+        assert!(runner.run(Box::new(program)).is_finished());
         assert_eq!(Option::Some(Coord::new(0,0,0)), runner.location().loc_absolute);
     }
 
@@ -24,7 +26,29 @@ mod tests {
         let program = InitGpsProgram::new();
         assert_eq!(0, runner.world().state.len());
         assert_eq!(Option::None, runner.location().loc_absolute);
-        runner.run(Box::new(program));
+        assert!(runner.run(Box::new(program)).is_finished());
         assert_eq!(Option::Some(Coord::new(2,0,0)), runner.location().loc_absolute);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn gps_timeouts_are_retried() {
+        let mut runner = Runner::make_world_unknown_loc_unknown("test_box", Coord::new(2, 0, 0), AxisDirection::Xp);
+        runner.set_faults(FaultConfig {gps_timeout: 0.5, ..FaultConfig::default()}, 3);
+        assert!(runner.run(Box::new(InitGpsProgram::new())).is_finished());
+        assert_eq!(Option::Some(Coord::new(2,0,0)), runner.location().loc_absolute);
+        assert!(runner.faults().unwrap().injected().iter().any(|(_, fault)| fault == &Fault::GpsTimeout));
+    }
+
+    #[test]
+    fn missing_gps_fails_the_program() {
+        let mut runner = Runner::make_world_unknown_loc_unknown("test_box", Coord::new(2, 0, 0), AxisDirection::Xp);
+        runner.set_gps_region(Some(Region {min: Coord::new(10, 0, 10), max: Coord::new(20, 0, 20)}));
+        match runner.run(Box::new(InitGpsProgram::new())) {
+            RunOutcome::Failed {steps, message} => {
+                assert_eq!(5, steps);
+                assert_eq!("No gps after 5 attempts", message);
+            },
+            x => panic!("Expected a failure, got {:?}", x)
+        }
+    }
+}
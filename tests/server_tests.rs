@@ -0,0 +1,149 @@
+mod fake_turtle;
+
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use fake_turtle::FakeTurtle;
+use turtlers::run_history::{RunEvent, RunHistory};
+use turtlers::world_simulator::Runner;
+
+/// The server binary, listening on a free local port until dropped
+struct Server {
+    process: Child,
+    addr: String,
+    runs_dir: String
+}
+
+impl Server {
+    fn start(name: &str) -> Self {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr = format!("127.0.0.1:{}", port);
+        let runs_dir = format!("{}/server_tests/{}", env!("CARGO_TARGET_TMPDIR"), name);
+        let _ = std::fs::remove_dir_all(&runs_dir);
+        let process = Command::new(env!("CARGO_BIN_EXE_turtlers"))
+            .args(["--bind", &addr, "--state-policy", "none", "--runs-dir", &runs_dir, "--verbosity", "quiet"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Killed on drop, also when it doesn't start
+        let server = Server {process, addr, runs_dir};
+        for _ in 0..100 {
+            if TcpStream::connect(&server.addr).is_ok() {
+                return server;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("Server did not start on {}", server.addr);
+    }
+
+    /// Events of the only run of the turtle
+    fn run_events(&self, id: &str) -> Vec<RunEvent> {
+        let runs: Vec<String> = std::fs::read_dir(&self.runs_dir).unwrap()
+            .map(|entry| entry.unwrap().path().to_string_lossy().to_string())
+            .filter(|path| path.contains(&format!("/{}_", id)))
+            .collect();
+        assert_eq!(1, runs.len(), "Runs of {}: {:?}", id, runs);
+        RunHistory::read(&runs[0]).unwrap()
+    }
+
+    /// Stops the server and returns what it printed
+    fn output(mut self) -> String {
+        let _ = self.process.kill();
+        let mut output = String::new();
+        self.process.stdout.take().unwrap().read_to_string(&mut output).unwrap();
+        output
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use turtlers::turtle_rotation::AxisDirection;
+    use turtlers::turtle_state::Coord;
+
+    #[test]
+    fn server_runs_programs_on_fake_turtle() {
+        let server = Server::start("runs_programs");
+        let runner = Runner::make_world_unknown_loc_unknown("test_box", Coord::new(2, 0, 0), AxisDirection::Xp);
+        let mut turtle = FakeTurtle::connect(&server.addr, "fake_gps", runner).unwrap();
+        let actions = turtle.run(&["initgps"]).unwrap();
+        assert!(actions > 0);
+        assert!(turtle.calls().iter().any(|call| call.cmd == "gps.locate"));
+
+        // The server knows where the turtle really is
+        let location = match server.run_events("fake_gps").last() {
+            Some(RunEvent::Action {location, ..}) => location.clone(),
+            x => panic!("No actions in the run, last event {:?}", x)
+        };
+        assert_eq!(actions, server.run_events("fake_gps").len() - 1);
+        assert_eq!(turtle.runner.shadow_location().loc_absolute, location.loc_absolute);
+        assert_eq!(turtle.runner.shadow_location().direction_absolute, location.direction_absolute);
+        turtle.close().unwrap();
+        // Quiet also silences the turtle logic, only errors are reported
+        let output = server.output();
+        assert!(!output.contains("Set program to") && !output.contains("Updating gps"), "{}", output);
+    }
+
+    #[test]
+    fn server_starts_programs_again() {
+        let server = Server::start("starts_again");
+        let runner = Runner::make_world_known_loc_known_originxp("test_box");
+        let mut turtle = FakeTurtle::connect(&server.addr, "fake_rotate", runner).unwrap();
+        assert_eq!(2, turtle.run(&["rotate", "2"]).unwrap());
+        assert_eq!(AxisDirection::Xm, turtle.runner.shadow_location().direction_absolute);
+        assert_eq!(1, turtle.run(&["rotate", "-1"]).unwrap());
+        assert_eq!(2 + 1 + 2, turtle.calls().len());
+        turtle.close().unwrap();
+    }
+
+    #[test]
+    fn server_refuses_duplicate_ids() {
+        let server = Server::start("duplicate_ids");
+        let first = FakeTurtle::connect(&server.addr, "fake_twin", Runner::make_world_known_loc_known_originxp("test_box")).unwrap();
+        let second = FakeTurtle::connect(&server.addr, "fake_twin", Runner::make_world_known_loc_known_originxp("test_box"));
+        assert!(second.is_err());
+        first.close().unwrap();
+    }
+
+    #[test]
+    fn server_exits_on_invalid_bind_address() {
+        let output = Command::new(env!("CARGO_BIN_EXE_turtlers"))
+            .args(["--bind", "not an address", "--state-policy", "none"])
+            .output()
+            .unwrap();
+        assert_eq!(Some(2), output.status.code());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Could not listen on not an address"));
+    }
+
+    #[test]
+    fn server_prints_help() {
+        let output = Command::new(env!("CARGO_BIN_EXE_turtlers")).arg("--help").output().unwrap();
+        assert_eq!(Some(0), output.status.code());
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("Usage: turtlers"));
+    }
+
+    #[test]
+    fn server_closes_after_malformed_messages() {
+        let server = Server::start("malformed");
+        let messages = ["not json", r#"{"msgtype":"start","args":["no_such_program"]}"#, r#"{"msgtype":"response","result":[true]}"#];
+        for (i, msg) in messages.iter().enumerate() {
+            let id = format!("fake_garbage_{}", i);
+            let mut turtle = FakeTurtle::connect(&server.addr, &id, Runner::make_world_known_loc_known_originxp("test_box")).unwrap();
+            assert!(turtle.send_raw(msg).is_err(), "{} was answered", msg);
+        }
+
+        // A response that doesn't fit the call stops the program too
+        let mut turtle = FakeTurtle::connect(&server.addr, "fake_wrong_result", Runner::make_world_known_loc_known_originxp("test_box")).unwrap();
+        assert_eq!("turtle.turnRight", turtle.start(&["rotate", "2"]).unwrap().cmd);
+        assert!(turtle.send_raw(r#"{"msgtype":"response","result":["no"]}"#).is_err());
+        assert_eq!(1, turtle.calls().len());
+    }
+}
@@ -0,0 +1,30 @@
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use turtlers::world_generator::WorldGenerator;
+use turtlers::world_simulator::Runner;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use turtlers::turtle_action::{dig, detect, go, inspect, place, turn, TurtleAction};
+
+    /// Mines around at random, the turtle has to keep track of where it is
+    #[test]
+    fn random_mining_in_generated_worlds() {
+        let actions: [TurtleAction; 14] = [
+            go::forward(), go::backward(), go::up(), go::down(), turn::left(), turn::right(),
+            dig::forward(), dig::up(), dig::down(), inspect::forward(), inspect::down(),
+            detect::forward(), place::forward(), place::down()
+        ];
+        let generator = WorldGenerator::default();
+        for seed in 0..30 {
+            let mut runner = Runner::generated(&generator, seed, true);
+            let mut rng = StdRng::seed_from_u64(seed);
+            for _ in 0..300 {
+                let action = actions.choose(&mut rng).unwrap();
+                runner.execute_action(action);
+                assert_eq!(runner.shadow_location().loc_absolute, runner.location().loc_absolute, "Lost track in world {}", seed);
+                assert_eq!(runner.shadow_location().direction_absolute, runner.location().direction_absolute);
+            }
+        }
+    }
+}